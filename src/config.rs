@@ -0,0 +1,259 @@
+/// Default number of slots reserved for the stack.
+const STACK_SIZE: usize = 50;
+
+/// Default upper limit on the number of stack slots, used
+/// when the stack is allowed to grow.
+const MAX_STACK_SIZE: usize = 1 << 20;
+
+/// Default number of addressable memory cells.
+const MEMORY_SIZE: usize = 256;
+
+/// Default number of heap cells that may be allocated at once.
+const HEAP_SIZE: usize = 1 << 16;
+
+/// Default maximum number of nested subroutine calls.
+const CALL_DEPTH: usize = 256;
+
+/// Default amount of fuel used up by each instruction.
+const FUEL_COST: u64 = 1;
+
+/// OverflowMode decides what happens when integer arithmetic produces
+/// a result that does not fit in 64 bits.
+///
+/// Wrapping: The result wraps around, as in two's complement arithmetic.
+/// Checked: The operation fails with a runtime error.
+/// Saturating: The result is clamped to the minimum or maximum integer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverflowMode {
+    Wrapping,
+    Checked,
+    Saturating
+}
+
+impl OverflowMode {
+    /// Looks up a mode by the name used on the command line and in
+    /// the .overflow directive.
+    pub fn from_name(name: &str) -> Option<OverflowMode> {
+        match name {
+            "wrapping" => Some(OverflowMode::Wrapping),
+            "checked" => Some(OverflowMode::Checked),
+            "saturating" => Some(OverflowMode::Saturating),
+            _ => None
+        }
+    }
+
+    /// Returns the name of the mode, as accepted by from_name.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            OverflowMode::Wrapping => "wrapping",
+            OverflowMode::Checked => "checked",
+            OverflowMode::Saturating => "saturating"
+        }
+    }
+}
+
+/// VmConfig holds the settings used to build a Vm. It is built
+/// by chaining calls on top of VmConfig::new(), which starts from
+/// the default values.
+///
+/// ## Example
+///
+/// ```
+/// use tyr::config::VmConfig;
+///
+/// let config = VmConfig::new()
+///     .stack_size(100)
+///     .max_stack_size(1000)
+///     .growable(true);
+///
+/// assert_eq!(config.get_stack_size(), 100);
+/// assert_eq!(config.get_max_stack_size(), 1000);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct VmConfig {
+    /// Number of stack slots reserved when the vm is created.
+    stack_size: usize,
+    /// Number of stack slots the stack may grow to.
+    max_stack_size: usize,
+    /// Whether the stack may grow past its initial size.
+    growable: bool,
+    /// Number of cells in the memory segment used by LOAD and STORE.
+    memory_size: usize,
+    /// Number of heap cells that may be allocated at once.
+    heap_size: usize,
+    /// Maximum number of return addresses held in the call stack.
+    call_depth: usize,
+    /// Behaviour of integer arithmetic that overflows.
+    overflow: OverflowMode,
+    /// Fuel available when the vm is created, or None to run without
+    /// any limit on the number of instructions executed.
+    fuel: Option<u64>,
+    /// Amount of fuel used up by each instruction.
+    fuel_cost: u64
+}
+
+impl Default for VmConfig {
+    fn default() -> VmConfig {
+        VmConfig::new()
+    }
+}
+
+impl VmConfig {
+    pub fn new() -> VmConfig {
+        VmConfig {
+            stack_size: STACK_SIZE,
+            max_stack_size: MAX_STACK_SIZE,
+            growable: false,
+            memory_size: MEMORY_SIZE,
+            heap_size: HEAP_SIZE,
+            call_depth: CALL_DEPTH,
+            overflow: OverflowMode::Wrapping,
+            fuel: None,
+            fuel_cost: FUEL_COST
+        }
+    }
+
+    /// Sets the number of stack slots reserved when the vm is created.
+    /// If the stack is not growable, this is also the maximum size.
+    pub fn stack_size(mut self, size: usize) -> VmConfig {
+        self.stack_size = size;
+        self
+    }
+
+    /// Sets the number of slots a growable stack may reach. This is
+    /// ignored unless the stack is growable.
+    pub fn max_stack_size(mut self, size: usize) -> VmConfig {
+        self.max_stack_size = size;
+        self
+    }
+
+    /// Allows the stack to grow past its initial size whenever it fills
+    /// up, until it reaches the maximum stack size.
+    pub fn growable(mut self, growable: bool) -> VmConfig {
+        self.growable = growable;
+        self
+    }
+
+    /// Sets the number of cells in the memory segment. Addresses used
+    /// by LOAD and STORE must be below this size.
+    pub fn memory_size(mut self, size: usize) -> VmConfig {
+        self.memory_size = size;
+        self
+    }
+
+    /// Sets the number of heap cells that may be allocated at once. An
+    /// ALLOC past this limit fails with an out of memory error.
+    pub fn heap_size(mut self, size: usize) -> VmConfig {
+        self.heap_size = size;
+        self
+    }
+
+    /// Sets the maximum number of nested subroutine calls.
+    pub fn call_depth(mut self, depth: usize) -> VmConfig {
+        self.call_depth = depth;
+        self
+    }
+
+    /// Sets how integer arithmetic behaves when it overflows. Integer
+    /// arithmetic wraps by default. A program can change this for
+    /// itself with the .overflow directive.
+    pub fn overflow(mut self, mode: OverflowMode) -> VmConfig {
+        self.overflow = mode;
+        self
+    }
+
+    /// Limits the amount of work the vm may do. Each instruction uses up
+    /// some fuel before it is executed, and the run stops once there is
+    /// not enough left. There is no limit by default.
+    pub fn fuel(mut self, fuel: u64) -> VmConfig {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    /// Sets the amount of fuel used up by each instruction.
+    pub fn fuel_cost(mut self, cost: u64) -> VmConfig {
+        self.fuel_cost = cost;
+        self
+    }
+
+    pub fn get_stack_size(&self) -> usize {
+        self.stack_size
+    }
+
+    pub fn get_max_stack_size(&self) -> usize {
+        self.max_stack_size
+    }
+
+    pub fn is_growable(&self) -> bool {
+        self.growable
+    }
+
+    pub fn get_memory_size(&self) -> usize {
+        self.memory_size
+    }
+
+    pub fn get_heap_size(&self) -> usize {
+        self.heap_size
+    }
+
+    pub fn get_call_depth(&self) -> usize {
+        self.call_depth
+    }
+
+    pub fn get_overflow(&self) -> OverflowMode {
+        self.overflow
+    }
+
+    pub fn get_fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    pub fn get_fuel_cost(&self) -> u64 {
+        self.fuel_cost
+    }
+
+    /// Returns the number of slots the stack can never grow past. For a
+    /// fixed size stack, this is the initial stack size.
+    pub fn stack_limit(&self) -> usize {
+        if self.growable {
+            self.max_stack_size.max(self.stack_size)
+        } else {
+            self.stack_size
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overflow_from_name() {
+        assert_eq!(OverflowMode::from_name("checked"), Some(OverflowMode::Checked));
+        assert_eq!(OverflowMode::from_name("saturating"), Some(OverflowMode::Saturating));
+        assert_eq!(OverflowMode::from_name("wrapping"), Some(OverflowMode::Wrapping));
+        assert_eq!(OverflowMode::from_name("Checked"), None);
+        assert_eq!(OverflowMode::from_name(OverflowMode::Saturating.name()), Some(OverflowMode::Saturating));
+    }
+
+    #[test]
+    fn test_stack_limit_fixed() {
+        let config = VmConfig::new().stack_size(10).max_stack_size(100);
+
+        assert_eq!(config.stack_limit(), 10);
+    }
+
+    #[test]
+    fn test_stack_limit_growable() {
+        let config = VmConfig::new().stack_size(10).max_stack_size(100).growable(true);
+
+        assert_eq!(config.stack_limit(), 100);
+    }
+
+    #[test]
+    fn test_stack_limit_max_below_initial() {
+        let config = VmConfig::new().stack_size(10).max_stack_size(5).growable(true);
+
+        assert_eq!(config.stack_limit(), 10);
+    }
+}
@@ -0,0 +1,33 @@
+/// A place in the program where a run should pause before executing
+/// the instruction there.
+///
+/// Pc: The instruction at an address in the program.
+/// Label: The first instruction after a label in the symbol table.
+/// Line: The instruction on a line of the source file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Breakpoint {
+    Pc(usize),
+    Label(String),
+    Line(usize)
+}
+
+/// A location that pauses the run whenever an instruction writes to it.
+///
+/// Stack: A stack slot, counted from the bottom of the stack.
+/// Memory: A cell of the memory segment used by LOAD and STORE.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Watchpoint {
+    Stack(usize),
+    Memory(usize)
+}
+
+/// Describes why a run paused.
+///
+/// Breakpoint: The pc reached a breakpoint at the given address. The
+///             instruction there has not been executed yet.
+/// Watchpoint: The instruction just executed wrote to the watched location.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pause {
+    Breakpoint(usize),
+    Watchpoint(Watchpoint)
+}
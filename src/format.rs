@@ -0,0 +1,280 @@
+use std::fmt;
+use value::{Value, ValueKind};
+
+/// Radix used to write an integer placeholder.
+///
+/// Decimal: The value is written with its Display format.
+/// Hex: `{:x}`, lower case hexadecimal.
+/// UpperHex: `{:X}`, upper case hexadecimal.
+/// Binary: `{:b}`, binary.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Radix {
+    Decimal,
+    Hex,
+    UpperHex,
+    Binary
+}
+
+/// Describes how a single placeholder in a template is written. This
+/// follows a small part of the syntax used by Rust's format! macro:
+/// `{:[0][width][x|X|b]}`. A leading 0 pads with zeros instead of
+/// spaces, and the width is the minimum number of characters written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Spec {
+    pub radix: Radix,
+    pub width: usize,
+    pub zero: bool
+}
+
+/// A piece of a template, either text copied as is or a placeholder
+/// filled with a value from the stack.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Piece {
+    Text(String),
+    Value(Spec)
+}
+
+/// A template for formatted output, as used by PRINTF. For example,
+/// `x={} y={:x}` writes two values, the first in decimal and the
+/// second in hexadecimal. Literal braces are written as `{{` and `}}`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    pieces: Vec<Piece>
+}
+
+impl Template {
+    /// Parses a template, returning a message describing the problem
+    /// if a placeholder is malformed or a brace is left unmatched.
+    pub fn parse(source: &str) -> Result<Template, String> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut chars = source.chars().peekable();
+
+        while let Some(ch) = chars.next() {
+            match ch {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                },
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                },
+                '{' => {
+                    let mut spec = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(ch) => spec.push(ch),
+                            None => return Err(format!("Unterminated placeholder in template {:?}", source))
+                        }
+                    }
+
+                    if !text.is_empty() {
+                        pieces.push(Piece::Text(text.clone()));
+                        text.clear();
+                    }
+                    pieces.push(Piece::Value(parse_spec(&spec)?));
+                },
+                '}' => return Err(format!("Unmatched '}}' in template {:?}", source)),
+                ch => text.push(ch)
+            }
+        }
+
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+
+        Ok(Template { pieces })
+    }
+
+    /// Returns the number of values needed to fill the template.
+    pub fn arity(&self) -> usize {
+        self.pieces.iter()
+            .filter(|piece| match **piece {
+                Piece::Value(_) => true,
+                Piece::Text(_) => false
+            })
+            .count()
+    }
+
+    /// Fills the template with the given values, in order. Hex and binary
+    /// placeholders only accept integers. If a value is of the wrong kind,
+    /// the kind that was expected is returned along with the value.
+    pub fn render(&self, values: &[Value]) -> Result<String, (ValueKind, Value)> {
+        let mut out = String::new();
+        let mut values = values.iter();
+
+        for piece in &self.pieces {
+            match *piece {
+                Piece::Text(ref text) => out.push_str(text),
+                Piece::Value(spec) => {
+                    let value = match values.next() {
+                        Some(value) => *value,
+                        None => break
+                    };
+                    out.push_str(&render_value(spec, value)?);
+                }
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+/// Writes the template in the syntax it is parsed from.
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for piece in &self.pieces {
+            match *piece {
+                Piece::Text(ref text) => f.write_str(&text.replace('{', "{{").replace('}', "}}"))?,
+                Piece::Value(spec) => {
+                    f.write_str("{")?;
+                    if spec.zero || spec.width > 0 || spec.radix != Radix::Decimal {
+                        f.write_str(":")?;
+                    }
+                    if spec.zero {
+                        f.write_str("0")?;
+                    }
+                    if spec.width > 0 {
+                        write!(f, "{}", spec.width)?;
+                    }
+                    f.write_str(match spec.radix {
+                        Radix::Decimal => "}",
+                        Radix::Hex => "x}",
+                        Radix::UpperHex => "X}",
+                        Radix::Binary => "b}"
+                    })?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses the part of a placeholder between the braces.
+fn parse_spec(spec: &str) -> Result<Spec, String> {
+    let mut result = Spec { radix: Radix::Decimal, width: 0, zero: false };
+    if spec.is_empty() {
+        return Ok(result);
+    }
+
+    if !spec.starts_with(':') {
+        return Err(format!("Invalid placeholder {{{}}}", spec));
+    }
+
+    let mut body = &spec[1..];
+    if body.starts_with('0') {
+        result.zero = true;
+        body = &body[1..];
+    }
+
+    let digits = body.chars().take_while(|ch| ch.is_ascii_digit()).count();
+    if digits > 0 {
+        result.width = body[..digits].parse::<usize>()
+            .map_err(|_| format!("Invalid width in placeholder {{{}}}", spec))?;
+    }
+
+    result.radix = match &body[digits..] {
+        "" => Radix::Decimal,
+        "x" => Radix::Hex,
+        "X" => Radix::UpperHex,
+        "b" => Radix::Binary,
+        _ => return Err(format!("Invalid placeholder {{{}}}", spec))
+    };
+
+    Ok(result)
+}
+
+/// Writes one value according to its placeholder.
+fn render_value(spec: Spec, value: Value) -> Result<String, (ValueKind, Value)> {
+    let width = spec.width;
+    let text = match (spec.radix, value) {
+        (Radix::Decimal, Value::Int(val)) if spec.zero => format!("{:0width$}", val, width = width),
+        (Radix::Decimal, Value::Float(val)) if spec.zero => format!("{:0width$?}", val, width = width),
+        (Radix::Decimal, value) => format!("{:>width$}", value.to_string(), width = width),
+        (Radix::Hex, Value::Int(val)) => pad(format!("{:x}", val), spec),
+        (Radix::UpperHex, Value::Int(val)) => pad(format!("{:X}", val), spec),
+        (Radix::Binary, Value::Int(val)) => pad(format!("{:b}", val), spec),
+        (_, value) => return Err((ValueKind::Int, value))
+    };
+
+    Ok(text)
+}
+
+/// Pads digits out to the width of a placeholder.
+fn pad(digits: String, spec: Spec) -> String {
+    let fill = if spec.zero { '0' } else { ' ' };
+    let mut out = String::new();
+    for _ in digits.len()..spec.width {
+        out.push(fill);
+    }
+    out.push_str(&digits);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let template = Template::parse("x={} y={:x}").unwrap();
+
+        assert_eq!(template.arity(), 2);
+        assert_eq!(template.render(&[Value::Int(3), Value::Int(255)]), Ok("x=3 y=ff".to_string()));
+    }
+
+    #[test]
+    fn test_display() {
+        let source = "{{{}}} {:X}|{:b}|{:08b}|{:5}|{:0}";
+
+        assert_eq!(Template::parse(source).unwrap().to_string(), source);
+    }
+
+    #[test]
+    fn test_render_formats() {
+        let template = Template::parse("{:X}|{:b}|{:08b}|{:5}|{:05}|{:4x}").unwrap();
+        let values = [
+            Value::Int(255),
+            Value::Int(5),
+            Value::Int(5),
+            Value::Int(42),
+            Value::Int(-42),
+            Value::Int(10)
+        ];
+
+        assert_eq!(template.render(&values), Ok("FF|101|00000101|   42|-0042|   a".to_string()));
+    }
+
+    #[test]
+    fn test_render_other_kinds() {
+        let template = Template::parse("{} {} {:6}").unwrap();
+        let values = [Value::Bool(true), Value::Addr(3), Value::Float(1.5)];
+
+        assert_eq!(template.render(&values), Ok("true @3    1.5".to_string()));
+    }
+
+    #[test]
+    fn test_render_hex_float() {
+        let template = Template::parse("{:x}").unwrap();
+
+        assert_eq!(template.render(&[Value::Float(1.5)]), Err((ValueKind::Int, Value::Float(1.5))));
+    }
+
+    #[test]
+    fn test_parse_escaped_braces() {
+        let template = Template::parse("{{{}}}").unwrap();
+
+        assert_eq!(template.render(&[Value::Int(1)]), Ok("{1}".to_string()));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Template::parse("x={").is_err());
+        assert!(Template::parse("x=}").is_err());
+        assert!(Template::parse("{:q}").is_err());
+        assert!(Template::parse("{0}").is_err());
+    }
+}
@@ -0,0 +1,417 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use value::Value;
+
+/// HeapErrors are returned when a program misuses heap memory.
+///
+/// OutOfMemory: An allocation would go past the heap size limit.
+/// InvalidSize: An allocation was requested with a size below one.
+/// IllegalAddress: An address does not point into any block.
+/// UseAfterFree: An address points into a block that has been freed.
+/// DoubleFree: A free was given an address inside memory that was already freed.
+/// InvalidFree: A free was given an address that is not the start of a block.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HeapError {
+    OutOfMemory(usize),
+    InvalidSize(i64),
+    IllegalAddress(usize),
+    UseAfterFree(usize),
+    DoubleFree(usize),
+    InvalidFree(usize)
+}
+
+impl fmt::Display for HeapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HeapError::OutOfMemory(size) => write!(f, "Out of heap memory allocating {} cells", size),
+            HeapError::InvalidSize(size) => write!(f, "Attempted to allocate {} cells", size),
+            HeapError::IllegalAddress(addr) => write!(f, "Attempted to access unallocated heap address {}", addr),
+            HeapError::UseAfterFree(addr) => write!(f, "Attempted to access freed heap address {}", addr),
+            HeapError::DoubleFree(addr) => write!(f, "Attempted to free heap block {} twice", addr),
+            HeapError::InvalidFree(addr) => write!(f, "Attempted to free {}, which is not the start of a heap block", addr)
+        }
+    }
+}
+
+pub type HeapResult<T> = Result<T, HeapError>;
+
+/// A block of heap memory that was never freed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Leak {
+    /// Address of the first cell in the block.
+    pub addr: usize,
+    /// Number of cells in the block.
+    pub size: usize
+}
+
+/// The full contents of a heap, used to save it in a snapshot and
+/// rebuild it later.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeapImage {
+    /// Live blocks, in address order.
+    pub blocks: Vec<(usize, Vec<Value>)>,
+    /// Address and size of each freed range, in address order.
+    pub freed: Vec<(usize, usize)>,
+    /// Address handed to the next allocation.
+    pub next_addr: usize
+}
+
+impl HeapImage {
+    /// Checks that the image describes a heap that could have been built
+    /// by allocating and freeing: every block and freed range is at least
+    /// one cell long, none of them start at address 0 or overlap each
+    /// other, and the next address is past all of them. Returns a message
+    /// describing the first problem found.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut ranges = Vec::with_capacity(self.blocks.len() + self.freed.len());
+        for &(addr, ref block) in &self.blocks {
+            ranges.push((addr, block.len()));
+        }
+        ranges.extend(self.freed.iter().cloned());
+        ranges.sort();
+
+        let mut end = 1;
+        for (addr, size) in ranges {
+            if size == 0 {
+                return Err(format!("Empty heap range at address {}", addr));
+            }
+            if addr < end {
+                return Err(format!("Overlapping heap range at address {}", addr));
+            }
+            end = match addr.checked_add(size) {
+                Some(end) => end,
+                None => return Err(format!("Heap range at address {} is too large", addr))
+            };
+        }
+
+        if self.next_addr < end {
+            return Err(format!("Next heap address {} is inside allocated memory", self.next_addr));
+        }
+
+        Ok(())
+    }
+}
+
+/// The Heap holds memory blocks allocated by a program at run time.
+///
+/// Every block is given a range of addresses that is never handed out
+/// again, even after the block is freed. This lets the heap tell a
+/// pointer into a freed block apart from one that was never valid,
+/// and catch use after free and double free errors. Address 0 is
+/// never allocated, so it can be used as a null pointer.
+///
+/// Only the ranges of freed addresses are remembered, not the blocks
+/// they came from. Freed blocks next to each other are merged into a
+/// single range, so a program that keeps allocating and freeing needs
+/// no more ranges than it has live blocks, plus one. The cost is that
+/// the freed address space itself is never reused.
+pub struct Heap {
+    /// Live blocks, keyed by the address of their first cell.
+    blocks: BTreeMap<usize, Vec<Value>>,
+    /// Sizes of the ranges of freed addresses, keyed by address. No two
+    /// ranges are next to each other.
+    freed: BTreeMap<usize, usize>,
+    /// Address handed to the next allocation.
+    next_addr: usize,
+    /// Number of cells held by live blocks.
+    used: usize,
+    /// Maximum number of cells that may be live at once.
+    limit: usize
+}
+
+impl Heap {
+    pub fn new(limit: usize) -> Heap {
+        Heap {
+            blocks: BTreeMap::new(),
+            freed: BTreeMap::new(),
+            next_addr: 1,
+            used: 0,
+            limit
+        }
+    }
+
+    /// Allocates a block of the given number of cells, all set to an
+    /// integer zero, and returns the address of its first cell.
+    pub fn alloc(&mut self, size: i64) -> HeapResult<usize> {
+        if size < 1 {
+            return Err(HeapError::InvalidSize(size));
+        }
+
+        let size = size as usize;
+        if self.used.saturating_add(size) > self.limit {
+            return Err(HeapError::OutOfMemory(size));
+        }
+
+        let addr = self.next_addr;
+        self.blocks.insert(addr, vec![Value::default(); size]);
+        self.next_addr += size;
+        self.used += size;
+
+        Ok(addr)
+    }
+
+    /// Frees the block starting at the given address.
+    pub fn free(&mut self, addr: usize) -> HeapResult<()> {
+        match self.blocks.remove(&addr) {
+            Some(block) => {
+                self.used -= block.len();
+                self.mark_freed(addr, block.len());
+                Ok(())
+            },
+            None if self.freed_range(addr).is_some() => Err(HeapError::DoubleFree(addr)),
+            None => Err(HeapError::InvalidFree(addr))
+        }
+    }
+
+    /// Records a range of addresses as freed, merging it with the freed
+    /// ranges right before and after it.
+    fn mark_freed(&mut self, addr: usize, size: usize) {
+        let mut start = addr;
+        let mut end = addr + size;
+
+        let before = self.freed.range(..addr).next_back().map(|(base, size)| (*base, *size));
+        if let Some((base, size)) = before {
+            if base + size == start {
+                self.freed.remove(&base);
+                start = base;
+            }
+        }
+        if let Some(size) = self.freed.remove(&end) {
+            end += size;
+        }
+
+        self.freed.insert(start, end - start);
+    }
+
+    /// Returns the start of the freed range holding an address, if any.
+    fn freed_range(&self, addr: usize) -> Option<usize> {
+        match self.freed.range(..=addr).next_back() {
+            Some((base, size)) if addr < base + size => Some(*base),
+            _ => None
+        }
+    }
+
+    /// Returns the value held at a heap address.
+    pub fn load(&self, addr: usize) -> HeapResult<Value> {
+        let (base, offset) = self.locate(addr)?;
+
+        Ok(self.blocks[&base][offset])
+    }
+
+    /// Writes a value to a heap address.
+    pub fn store(&mut self, addr: usize, value: Value) -> HeapResult<()> {
+        let (base, offset) = self.locate(addr)?;
+
+        if let Some(block) = self.blocks.get_mut(&base) {
+            block[offset] = value;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the blocks that are still allocated, in address order.
+    pub fn leaks(&self) -> Vec<Leak> {
+        self.blocks.iter()
+            .map(|(addr, block)| Leak { addr: *addr, size: block.len() })
+            .collect()
+    }
+
+    /// Returns a copy of everything held by the heap.
+    pub fn image(&self) -> HeapImage {
+        HeapImage {
+            blocks: self.blocks.iter().map(|(addr, block)| (*addr, block.clone())).collect(),
+            freed: self.freed.iter().map(|(addr, size)| (*addr, *size)).collect(),
+            next_addr: self.next_addr
+        }
+    }
+
+    /// Rebuilds a heap from an image. The limit is not part of the image,
+    /// so the heap may hold more cells than the limit allows, in which case
+    /// further allocations fail until enough blocks are freed.
+    pub fn from_image(image: HeapImage, limit: usize) -> Heap {
+        let used = image.blocks.iter().map(|(_, block)| block.len()).sum();
+
+        Heap {
+            blocks: image.blocks.into_iter().collect(),
+            freed: image.freed.into_iter().collect(),
+            next_addr: image.next_addr,
+            used,
+            limit
+        }
+    }
+
+    /// Finds the live block containing an address, returning the block's
+    /// address along with the offset of the address inside of it.
+    fn locate(&self, addr: usize) -> HeapResult<(usize, usize)> {
+        if let Some((base, block)) = self.blocks.range(..=addr).next_back() {
+            if addr < base + block.len() {
+                return Ok((*base, addr - base));
+            }
+        }
+
+        if self.freed_range(addr).is_some() {
+            return Err(HeapError::UseAfterFree(addr));
+        }
+
+        Err(HeapError::IllegalAddress(addr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alloc_store_load() {
+        let mut heap = Heap::new(16);
+        let addr = heap.alloc(4).unwrap();
+        heap.store(addr + 3, Value::Int(7)).unwrap();
+
+        assert_eq!(heap.load(addr + 3), Ok(Value::Int(7)));
+        assert_eq!(heap.load(addr), Ok(Value::Int(0)));
+    }
+
+    #[test]
+    fn test_alloc_out_of_memory() {
+        let mut heap = Heap::new(4);
+        heap.alloc(3).unwrap();
+
+        assert_eq!(heap.alloc(2), Err(HeapError::OutOfMemory(2)));
+    }
+
+    #[test]
+    fn test_alloc_invalid_size() {
+        let mut heap = Heap::new(4);
+
+        assert_eq!(heap.alloc(0), Err(HeapError::InvalidSize(0)));
+    }
+
+    #[test]
+    fn test_free_reclaims_space() {
+        let mut heap = Heap::new(4);
+        let addr = heap.alloc(4).unwrap();
+        heap.free(addr).unwrap();
+
+        assert!(heap.alloc(4).is_ok());
+    }
+
+    #[test]
+    fn test_use_after_free() {
+        let mut heap = Heap::new(16);
+        let addr = heap.alloc(2).unwrap();
+        heap.free(addr).unwrap();
+        heap.alloc(2).unwrap();
+
+        assert_eq!(heap.load(addr + 1), Err(HeapError::UseAfterFree(addr + 1)));
+        assert_eq!(heap.store(addr, Value::Int(1)), Err(HeapError::UseAfterFree(addr)));
+    }
+
+    #[test]
+    fn test_double_free() {
+        let mut heap = Heap::new(16);
+        let addr = heap.alloc(2).unwrap();
+        heap.free(addr).unwrap();
+
+        assert_eq!(heap.free(addr), Err(HeapError::DoubleFree(addr)));
+    }
+
+    #[test]
+    fn test_invalid_free() {
+        let mut heap = Heap::new(16);
+        let addr = heap.alloc(2).unwrap();
+
+        assert_eq!(heap.free(addr + 1), Err(HeapError::InvalidFree(addr + 1)));
+        assert_eq!(heap.free(0), Err(HeapError::InvalidFree(0)));
+    }
+
+    #[test]
+    fn test_freed_ranges_merge() {
+        let mut heap = Heap::new(16);
+        let kept = heap.alloc(1).unwrap();
+        for _ in 0..100 {
+            let addr = heap.alloc(4).unwrap();
+            heap.free(addr).unwrap();
+        }
+        let first = heap.alloc(2).unwrap();
+        let second = heap.alloc(2).unwrap();
+        heap.free(second).unwrap();
+        heap.free(first).unwrap();
+
+        assert_eq!(heap.image().freed, vec![(kept + 1, 404)]);
+        assert_eq!(heap.free(second), Err(HeapError::DoubleFree(second)));
+        assert_eq!(heap.load(kept + 404), Err(HeapError::UseAfterFree(kept + 404)));
+        assert_eq!(heap.load(kept + 405), Err(HeapError::IllegalAddress(kept + 405)));
+    }
+
+    #[test]
+    fn test_illegal_address() {
+        let mut heap = Heap::new(16);
+        let addr = heap.alloc(2).unwrap();
+
+        assert_eq!(heap.load(addr + 2), Err(HeapError::IllegalAddress(addr + 2)));
+        assert_eq!(heap.load(0), Err(HeapError::IllegalAddress(0)));
+    }
+
+    #[test]
+    fn test_image_round_trip() {
+        let mut heap = Heap::new(16);
+        let first = heap.alloc(2).unwrap();
+        let second = heap.alloc(3).unwrap();
+        heap.store(second + 1, Value::Int(9)).unwrap();
+        heap.free(first).unwrap();
+
+        let mut copy = Heap::from_image(heap.image(), 16);
+
+        assert_eq!(copy.image(), heap.image());
+        assert_eq!(copy.load(second + 1), Ok(Value::Int(9)));
+        assert_eq!(copy.load(first), Err(HeapError::UseAfterFree(first)));
+        assert_eq!(copy.alloc(14), Err(HeapError::OutOfMemory(14)));
+    }
+
+    #[test]
+    fn test_image_into_smaller_heap() {
+        let mut heap = Heap::new(16);
+        let first = heap.alloc(6).unwrap();
+        heap.alloc(6).unwrap();
+
+        let mut copy = Heap::from_image(heap.image(), 8);
+
+        assert_eq!(copy.alloc(1), Err(HeapError::OutOfMemory(1)));
+        copy.free(first).unwrap();
+        assert_eq!(copy.alloc(3), Err(HeapError::OutOfMemory(3)));
+        assert!(copy.alloc(2).is_ok());
+    }
+
+    #[test]
+    fn test_image_validate() {
+        let mut heap = Heap::new(16);
+        let first = heap.alloc(2).unwrap();
+        heap.alloc(3).unwrap();
+        heap.free(first).unwrap();
+        let image = heap.image();
+
+        assert_eq!(image.validate(), Ok(()));
+
+        let mut overlapping = image.clone();
+        overlapping.freed[0].1 = 3;
+        assert!(overlapping.validate().is_err());
+
+        let mut behind = image.clone();
+        behind.next_addr = 4;
+        assert!(behind.validate().is_err());
+
+        let mut null = image.clone();
+        null.freed[0] = (0, 1);
+        assert!(null.validate().is_err());
+    }
+
+    #[test]
+    fn test_leaks() {
+        let mut heap = Heap::new(16);
+        let first = heap.alloc(2).unwrap();
+        let second = heap.alloc(3).unwrap();
+        heap.free(first).unwrap();
+
+        assert_eq!(heap.leaks(), vec![Leak { addr: second, size: 3 }]);
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use value::Value;
+
+/// The result of a host function: the values to push on success, or
+/// a message describing why the function failed.
+pub type HostResult = Result<Vec<Value>, String>;
+
+/// A native function registered by the program embedding the vm. It
+/// is given its arguments in the order they were pushed.
+pub type HostFn = Box<dyn FnMut(&[Value]) -> HostResult>;
+
+/// A host function along with the number of values it takes from the
+/// stack and the number of values it pushes back.
+pub struct HostFunction {
+    pub arity: usize,
+    pub returns: usize,
+    pub func: HostFn
+}
+
+/// HostRegistry holds the native functions that a tyr program can call
+/// with CALLN, keyed by name.
+#[derive(Default)]
+pub struct HostRegistry {
+    functions: HashMap<String, HostFunction>
+}
+
+impl HostRegistry {
+    pub fn new() -> HostRegistry {
+        HostRegistry {
+            functions: HashMap::new()
+        }
+    }
+
+    /// Adds a function to the registry. A function registered under a
+    /// name that is already taken replaces the existing one.
+    pub fn insert(&mut self, name: String, function: HostFunction) {
+        self.functions.insert(name, function);
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut HostFunction> {
+        self.functions.get_mut(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_replaces() {
+        let mut registry = HostRegistry::new();
+        registry.insert("f".to_string(), HostFunction {
+            arity: 0,
+            returns: 1,
+            func: Box::new(|_| Ok(vec![Value::Int(1)]))
+        });
+        registry.insert("f".to_string(), HostFunction {
+            arity: 0,
+            returns: 1,
+            func: Box::new(|_| Ok(vec![Value::Int(2)]))
+        });
+
+        let function = registry.get_mut("f").unwrap();
+        assert_eq!((function.func)(&[]), Ok(vec![Value::Int(2)]));
+        assert!(!registry.contains("g"));
+    }
+}
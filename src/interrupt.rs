@@ -0,0 +1,57 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// InterruptHandle stops a running vm from outside of it, for example
+/// from another thread once a deadline has passed. Handles are created
+/// with Vm::interrupt_handle, and every clone of a handle refers to the
+/// same vm.
+///
+/// The vm checks for an interrupt before each instruction, and before
+/// reporting that the program has finished. When it sees one, it stops
+/// with ExitState::Interrupted and clears the interrupt, so the run can
+/// be continued by calling run again.
+#[derive(Clone, Debug, Default)]
+pub struct InterruptHandle {
+    flag: Arc<AtomicBool>
+}
+
+impl InterruptHandle {
+    pub fn new() -> InterruptHandle {
+        InterruptHandle {
+            flag: Arc::new(AtomicBool::new(false))
+        }
+    }
+
+    /// Asks the vm to stop before its next instruction.
+    pub fn interrupt(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns whether an interrupt is waiting to be seen by the vm.
+    pub fn is_interrupted(&self) -> bool {
+        self.flag.load(Ordering::SeqCst)
+    }
+
+    /// Clears a waiting interrupt, returning whether there was one.
+    pub fn take(&self) -> bool {
+        self.flag.swap(false, Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_interrupt_shared_between_clones() {
+        let handle = InterruptHandle::new();
+        let other = handle.clone();
+
+        thread::spawn(move || other.interrupt()).join().unwrap();
+
+        assert!(handle.is_interrupted());
+        assert!(handle.take());
+        assert!(!handle.is_interrupted());
+    }
+}
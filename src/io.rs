@@ -0,0 +1,134 @@
+use std::io::{self, BufRead, BufReader, Cursor, Stderr, Stdin, Stdout, Write};
+
+/// Io supplies the streams used by instructions that talk to the outside
+/// world. PRINT writes to stdout, EPRINT writes to stderr and READ reads
+/// lines from stdin. The vm is generic over this trait, so that an
+/// embedder can route a program's I/O wherever it likes.
+pub trait Io {
+    fn stdout(&mut self) -> &mut dyn Write;
+    fn stderr(&mut self) -> &mut dyn Write;
+    fn stdin(&mut self) -> &mut dyn BufRead;
+}
+
+/// StdIo connects a program to the standard streams of the process.
+/// This is what Vm::new uses.
+pub struct StdIo {
+    stdout: Stdout,
+    stderr: Stderr,
+    stdin: BufReader<Stdin>
+}
+
+impl StdIo {
+    pub fn new() -> StdIo {
+        StdIo {
+            stdout: io::stdout(),
+            stderr: io::stderr(),
+            stdin: BufReader::new(io::stdin())
+        }
+    }
+}
+
+impl Default for StdIo {
+    fn default() -> StdIo {
+        StdIo::new()
+    }
+}
+
+impl Io for StdIo {
+    fn stdout(&mut self) -> &mut dyn Write {
+        &mut self.stdout
+    }
+
+    fn stderr(&mut self) -> &mut dyn Write {
+        &mut self.stderr
+    }
+
+    fn stdin(&mut self) -> &mut dyn BufRead {
+        &mut self.stdin
+    }
+}
+
+/// MemoryIo keeps a program's I/O in memory. Input is read from a
+/// string given up front, and everything written is collected so it
+/// can be inspected once the program is done.
+///
+/// ## Example
+///
+/// ```
+/// use tyr::config::VmConfig;
+/// use tyr::io::MemoryIo;
+/// use tyr::op::OpCode;
+/// use tyr::program::Program;
+/// use tyr::sym_tab::SymbolTable;
+/// use tyr::vm::Vm;
+///
+/// let prog = vec![OpCode::PRINT("Hello!".to_string())];
+/// let sym_tab = SymbolTable::new();
+/// let mut vm = Vm::with_io(Program::new(prog, sym_tab), VmConfig::new(), MemoryIo::new(""));
+/// vm.run().unwrap();
+///
+/// assert_eq!(vm.io().output(), "Hello!\n");
+/// ```
+pub struct MemoryIo {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    stdin: Cursor<Vec<u8>>
+}
+
+impl MemoryIo {
+    pub fn new(input: &str) -> MemoryIo {
+        MemoryIo {
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            stdin: Cursor::new(input.as_bytes().to_vec())
+        }
+    }
+
+    /// Returns everything written to stdout so far.
+    pub fn output(&self) -> String {
+        String::from_utf8_lossy(&self.stdout).into_owned()
+    }
+
+    /// Returns everything written to stderr so far.
+    pub fn errors(&self) -> String {
+        String::from_utf8_lossy(&self.stderr).into_owned()
+    }
+}
+
+impl Io for MemoryIo {
+    fn stdout(&mut self) -> &mut dyn Write {
+        &mut self.stdout
+    }
+
+    fn stderr(&mut self) -> &mut dyn Write {
+        &mut self.stderr
+    }
+
+    fn stdin(&mut self) -> &mut dyn BufRead {
+        &mut self.stdin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_io_output() {
+        let mut io = MemoryIo::new("");
+        writeln!(io.stdout(), "out").unwrap();
+        writeln!(io.stderr(), "err").unwrap();
+
+        assert_eq!(io.output(), "out\n");
+        assert_eq!(io.errors(), "err\n");
+    }
+
+    #[test]
+    fn test_memory_io_input() {
+        let mut io = MemoryIo::new("1\n2\n");
+        let mut line = String::new();
+        io.stdin().read_line(&mut line).unwrap();
+
+        assert_eq!(line, "1\n");
+    }
+}
@@ -1,5 +1,18 @@
 pub mod op;
 pub mod vm;
 pub mod parser;
+pub mod program;
 pub mod sym_tab;
 pub mod util;
+pub mod config;
+pub mod heap;
+pub mod value;
+pub mod io;
+pub mod format;
+pub mod host;
+pub mod interrupt;
+pub mod debug;
+pub mod trace;
+pub mod profile;
+pub mod snapshot;
+pub mod token;
@@ -1,31 +1,179 @@
 extern crate tyr;
 
 use std::env;
-use tyr::vm::Vm;
-use tyr::parser::Parser;
-use tyr::sym_tab::SymbolTable;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::process;
+use std::sync::Arc;
+use tyr::config::{OverflowMode, VmConfig};
+use tyr::vm::{ExitState, Vm};
+use tyr::profile::Profiler;
+use tyr::program::Program;
+use tyr::trace::Tracer;
 use tyr::util;
 
+const USAGE: &str = "usage: tyr [options] <file>
+
+options:
+    --stack-size <n>        Number of stack slots allocated at startup.
+    --max-stack-size <n>    Number of slots a growable stack may reach.
+    --grow-stack            Allow the stack to grow when it fills up.
+    --memory-size <n>       Number of addressable memory cells.
+    --heap-size <n>         Number of heap cells that may be allocated at once.
+    --call-depth <n>        Maximum number of nested subroutine calls.
+    --overflow <mode>       Integer overflow behaviour: wrapping, checked
+                            or saturating. Defaults to wrapping.
+    --fuel <n>              Stop after using up n units of fuel. Each
+                            instruction uses one unit.
+    --trace <file>          Write a JSON line to file for each executed
+                            instruction.
+    --trace-from <label>    Only trace instructions from this label on.
+    --trace-to <label>      Only trace instructions before this label.
+    --profile <file>        Write a report of the instructions executed
+                            most often to file.
+    --folded <file>         Write the profiled call stacks to file, in the
+                            folded format read by flamegraph tools.";
+
+/// Settings parsed from the command line.
+struct Args {
+    filename: String,
+    config: VmConfig,
+    trace: Option<String>,
+    trace_from: Option<String>,
+    trace_to: Option<String>,
+    profile: Option<String>,
+    folded: Option<String>
+}
+
 fn main() {
-    let filename = env::args().nth(1).unwrap_or_else(|| {
-        panic!("tyr: Expected an input file to execute.");
+    let args = parse_args(env::args().skip(1).collect()).unwrap_or_else(|error| {
+        eprintln!("tyr: {}\n\n{}", error, USAGE);
+        process::exit(2);
     });
 
-    // Declare our symbol table and our program
-    // vectors vars here. Then, in a scope block, we allow
-    // the parser to mutably borrow the symbol table and insert
-    // entries in to it. Once that borrow is over, we can
-    // pass a reference of that symbol table to the vm
-    // and use it.
-    let mut sym_tab = SymbolTable::new();
-    let prog;
-    {
-        let parser = Parser::new(&mut sym_tab);
-        prog = util::read_file(filename, parser);
-    }
     // TODO: Could make this JIT by using the parser in vm,
     // parse a line and then executing it in the execute loop
-    let mut vm = Vm::new(&prog, &sym_tab);
+    let program = Arc::new(util::read_file(args.filename));
+    let mut vm = Vm::new(Arc::clone(&program), args.config);
+
+    if let Some(ref path) = args.trace {
+        let range = (args.trace_from.as_ref(), args.trace_to.as_ref());
+        let tracer = make_tracer(path, range, &program).unwrap_or_else(|error| {
+            eprintln!("tyr: {}", error);
+            process::exit(2);
+        });
+        vm.set_tracer(tracer);
+    }
+
+    if args.profile.is_some() || args.folded.is_some() {
+        vm.set_profiler(Profiler::new(program.sym_tab()));
+    }
+
+    let result = vm.run();
+
+    if let Some(profiler) = vm.profiler() {
+        let outputs = vec![(&args.profile, profiler.report()), (&args.folded, profiler.folded())];
+        for (path, contents) in outputs {
+            if let Some(ref path) = *path {
+                if let Err(error) = fs::write(path, contents) {
+                    eprintln!("tyr: Cannot write profile {}: {}", path, error);
+                }
+            }
+        }
+    }
+
+    match result {
+        Ok(ExitState::OutOfFuel) => {
+            eprintln!("tyr: Ran out of fuel");
+            process::exit(1);
+        },
+        Ok(_) => {},
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    }
+
+    for leak in vm.heap().leaks() {
+        eprintln!("tyr: Leaked {} heap cells at address {}", leak.size, leak.addr);
+    }
+}
+
+/// Creates the trace file, and limits tracing to the instructions between
+/// the --trace-from and --trace-to labels when they are given. A label
+/// points at the instruction after its own line, so the range ends one
+/// address earlier to leave out the --trace-to label line itself.
+fn make_tracer(path: &str, range: (Option<&String>, Option<&String>),
+               program: &Program) -> Result<Tracer, String> {
+    let label_addr = |label: &String| program.label(label)
+        .ok_or_else(|| format!("Unknown trace label {:?}", label));
+    let start = match range.0 {
+        Some(label) => label_addr(label)?,
+        None => 0
+    };
+    let end = match range.1 {
+        Some(label) => label_addr(label)?.saturating_sub(1),
+        None => program.len()
+    };
+
+    let file = File::create(path).map_err(|e| format!("Cannot create trace file {}: {}", path, e))?;
+
+    Ok(Tracer::new(BufWriter::new(file)).range(start..end))
+}
+
+/// Reads the options and input file name out of the command line
+/// arguments. Returns a message describing the problem if an option
+/// is unknown, is missing its value, or no input file is given.
+fn parse_args(argv: Vec<String>) -> Result<Args, String> {
+    let mut filename = None;
+    let mut config = VmConfig::new();
+    let mut trace = None;
+    let mut trace_from = None;
+    let mut trace_to = None;
+    let mut profile = None;
+    let mut folded = None;
+    let mut iter = argv.into_iter();
+
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--stack-size" => config = config.stack_size(flag_value(&arg, iter.next())?),
+            "--max-stack-size" => config = config.max_stack_size(flag_value(&arg, iter.next())?),
+            "--grow-stack" => config = config.growable(true),
+            "--memory-size" => config = config.memory_size(flag_value(&arg, iter.next())?),
+            "--heap-size" => config = config.heap_size(flag_value(&arg, iter.next())?),
+            "--call-depth" => config = config.call_depth(flag_value(&arg, iter.next())?),
+            "--fuel" => config = config.fuel(flag_value(&arg, iter.next())? as u64),
+            "--overflow" => {
+                let name = string_value(&arg, iter.next())?;
+                let mode = OverflowMode::from_name(&name)
+                    .ok_or_else(|| format!("Invalid value {:?} for option {}", name, arg))?;
+                config = config.overflow(mode);
+            },
+            "--trace" => trace = Some(string_value(&arg, iter.next())?),
+            "--trace-from" => trace_from = Some(string_value(&arg, iter.next())?),
+            "--trace-to" => trace_to = Some(string_value(&arg, iter.next())?),
+            "--profile" => profile = Some(string_value(&arg, iter.next())?),
+            "--folded" => folded = Some(string_value(&arg, iter.next())?),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ => filename = Some(arg)
+        }
+    }
+
+    match filename {
+        Some(filename) => Ok(Args { filename, config, trace, trace_from, trace_to, profile, folded }),
+        None => Err("Expected an input file to execute.".to_string())
+    }
+}
+
+/// Returns the value given to a command line option.
+fn string_value(flag: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or_else(|| format!("Missing value for option {}", flag))
+}
+
+/// Parses the value given to a numeric command line option.
+fn flag_value(flag: &str, value: Option<String>) -> Result<usize, String> {
+    let value = string_value(flag, value)?;
 
-    vm.run();
+    value.parse::<usize>()
+        .map_err(|_| format!("Invalid value {:?} for option {}", value, flag))
 }
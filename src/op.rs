@@ -1,4 +1,7 @@
-use std::num::ParseIntError;
+use config::OverflowMode;
+use format::Template;
+use std::fmt;
+use std::num::{ParseFloatError, ParseIntError};
 
 /// Contains the name of each operation that can be performed
 /// by the vm. Any operations that require arguments to
@@ -6,7 +9,12 @@ use std::num::ParseIntError;
 #[derive(Clone, Debug, PartialEq)]
 pub enum OpCode {
     PRINT(String),
+    PRINTV,
+    PRINTF(Template),
+    EPRINT(String),
+    READ,
     LOADC(i64),
+    LOADF(f64),
     LOADV(i64),
     LABEL(String, usize),
     LOAD,
@@ -20,23 +28,176 @@ pub enum OpCode {
     AND,
     OR,
     NEG,
+    EQ,
+    NE,
+    LT,
+    LE,
+    GT,
+    GE,
+    FADD,
+    FSUB,
+    FMUL,
+    FDIV,
+    ITOF,
+    FTOI,
     HALT,
     JMP(String),
     JMPZ(String),
+    JMPNZ(String),
+    JEQ(String),
+    JNE(String),
+    JLT(String),
+    JLE(String),
+    JGT(String),
+    JGE(String),
     JMPI(i64),
+    CALL(String),
+    CALLN(String),
+    RET,
+    TRAP(String),
+    ALLOC(i64),
+    FREE,
+    LOADH,
+    STOREH,
     DUP,
+    SWAP,
+    DROP,
+    OVER,
+    ROT,
+    PICK(i64),
+    DEPTH,
+    OVERFLOW(OverflowMode),
     NOP
 }
 
+impl OpCode {
+    /// Returns the mnemonic of the operation, without its arguments.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            OpCode::PRINT(_) => "PRINT",
+            OpCode::PRINTV => "PRINTV",
+            OpCode::PRINTF(_) => "PRINTF",
+            OpCode::EPRINT(_) => "EPRINT",
+            OpCode::READ => "READ",
+            OpCode::LOADC(_) => "LOADC",
+            OpCode::LOADF(_) => "LOADF",
+            OpCode::LOADV(_) => "LOADV",
+            OpCode::LABEL(_, _) => "LABEL",
+            OpCode::LOAD => "LOAD",
+            OpCode::STORE => "STORE",
+            OpCode::STOREV(_) => "STOREV",
+            OpCode::ADD => "ADD",
+            OpCode::SUB => "SUB",
+            OpCode::MUL => "MUL",
+            OpCode::DIV => "DIV",
+            OpCode::MOD => "MOD",
+            OpCode::AND => "AND",
+            OpCode::OR => "OR",
+            OpCode::NEG => "NEG",
+            OpCode::EQ => "EQ",
+            OpCode::NE => "NE",
+            OpCode::LT => "LT",
+            OpCode::LE => "LE",
+            OpCode::GT => "GT",
+            OpCode::GE => "GE",
+            OpCode::FADD => "FADD",
+            OpCode::FSUB => "FSUB",
+            OpCode::FMUL => "FMUL",
+            OpCode::FDIV => "FDIV",
+            OpCode::ITOF => "ITOF",
+            OpCode::FTOI => "FTOI",
+            OpCode::HALT => "HALT",
+            OpCode::JMP(_) => "JMP",
+            OpCode::JMPZ(_) => "JMPZ",
+            OpCode::JMPNZ(_) => "JMPNZ",
+            OpCode::JEQ(_) => "JEQ",
+            OpCode::JNE(_) => "JNE",
+            OpCode::JLT(_) => "JLT",
+            OpCode::JLE(_) => "JLE",
+            OpCode::JGT(_) => "JGT",
+            OpCode::JGE(_) => "JGE",
+            OpCode::JMPI(_) => "JMPI",
+            OpCode::CALL(_) => "CALL",
+            OpCode::CALLN(_) => "CALLN",
+            OpCode::RET => "RET",
+            OpCode::TRAP(_) => "TRAP",
+            OpCode::ALLOC(_) => "ALLOC",
+            OpCode::FREE => "FREE",
+            OpCode::LOADH => "LOADH",
+            OpCode::STOREH => "STOREH",
+            OpCode::DUP => "DUP",
+            OpCode::SWAP => "SWAP",
+            OpCode::DROP => "DROP",
+            OpCode::OVER => "OVER",
+            OpCode::ROT => "ROT",
+            OpCode::PICK(_) => "PICK",
+            OpCode::DEPTH => "DEPTH",
+            OpCode::OVERFLOW(_) => "OVERFLOW",
+            OpCode::NOP => "NOP"
+        }
+    }
+}
+
+/// Writes an instruction the way it appears in a tyr program: the name
+/// of the operation followed by its operand, if it has one. Text is
+/// written as a string literal, so the result parses back into the same
+/// instruction.
+impl fmt::Display for OpCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OpCode::PRINT(ref text) | OpCode::EPRINT(ref text) => write!(f, "{} {}", self.name(), quote(text)),
+            OpCode::PRINTF(ref template) => write!(f, "{} {}", self.name(), quote(&template.to_string())),
+            OpCode::LOADC(val) | OpCode::LOADV(val) | OpCode::STOREV(val) |
+            OpCode::JMPI(val) | OpCode::ALLOC(val) | OpCode::PICK(val) => write!(f, "{} {}", self.name(), val),
+            OpCode::LOADF(val) => write!(f, "{} {:?}", self.name(), val),
+            OpCode::LABEL(ref label, _) => write!(f, "{}:", label),
+            OpCode::JMP(ref label) | OpCode::JMPZ(ref label) | OpCode::JMPNZ(ref label) |
+            OpCode::JEQ(ref label) | OpCode::JNE(ref label) | OpCode::JLT(ref label) |
+            OpCode::JLE(ref label) | OpCode::JGT(ref label) | OpCode::JGE(ref label) |
+            OpCode::CALL(ref label) | OpCode::CALLN(ref label) | OpCode::TRAP(ref label) => {
+                write!(f, "{} {}", self.name(), label)
+            },
+            OpCode::OVERFLOW(mode) => write!(f, ".overflow {}", mode.name()),
+            _ => f.write_str(self.name())
+        }
+    }
+}
+
+/// Writes text as a tyr string literal, escaping the characters that
+/// cannot appear in it as they are.
+fn quote(text: &str) -> String {
+    let mut out = String::from("\"");
+    for ch in text.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            ch if ch.is_control() => out.push_str(&format!("\\u{{{:x}}}", ch as u32)),
+            ch => out.push(ch)
+        }
+    }
+    out.push('"');
+    out
+}
+
 /// OpErrors are used by methods in the vm and parser that
 /// need to return a Result type.
 ///
 /// Parse: Encountered when trying to parse a string to an int.
+/// ParseFloat: Encountered when trying to parse a string to a float.
 /// Lavel: Encountered when trying to jmp to or parse a label.
+/// Directive: Encountered when a directive or its argument is not recognized.
+/// Format: Encountered when the template given to PRINTF is malformed.
+/// Syntax: Encountered when a line is missing part of an operation.
 #[derive(Clone, Debug, PartialEq)]
 pub enum OpError {
     Parse(ParseIntError),
-    Label(String)
+    ParseFloat(ParseFloatError),
+    Label(String),
+    Directive(String),
+    Format(String),
+    Syntax(String)
 }
 
 impl From<ParseIntError> for OpError {
@@ -44,3 +205,9 @@ impl From<ParseIntError> for OpError {
         OpError::Parse(err)
     }
 }
+
+impl From<ParseFloatError> for OpError {
+    fn from(err: ParseFloatError) -> OpError {
+        OpError::ParseFloat(err)
+    }
+}
@@ -1,62 +1,112 @@
+use config::OverflowMode;
+use format::Template;
 use op::{OpCode, OpError};
+use program::Program;
 use sym_tab::SymbolTable;
+use token::{self, Token};
 
 /// The Parser is responsible for converting strings in a file to
 /// OpCodes that can be executed by the vm. The struct also holds
 /// some info for error reporting, as well as a symbol table to
 /// insert label names.
-pub struct Parser<'s> {
+pub struct Parser {
     // TODO: Hold some more error handling info here
     // line number, char number, etc.
     /// Current line being parsed.
     line: usize,
     /// Symbol table for execution of this program.
-    sym_tab: &'s mut SymbolTable
+    sym_tab: SymbolTable
 }
 
 pub type ParseResult = Result<OpCode, OpError>;
 pub type ArgResult = Result<i64, OpError>;
+pub type FloatArgResult = Result<f64, OpError>;
 
-impl<'s> Parser<'s> {
-    pub fn new(table: &'s mut SymbolTable) -> Parser {
+impl Default for Parser {
+    fn default() -> Parser {
+        Parser::new()
+    }
+}
+
+impl Parser {
+    pub fn new() -> Parser {
         Parser {
             line: 1,
-            sym_tab: table
+            sym_tab: SymbolTable::new()
         }
     }
 
+    /// Parses every line of a program's source, and returns the parsed
+    /// Program along with the labels it declares and the line each of its
+    /// instructions came from. Parsing stops at the first line with an error.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use tyr::op::OpCode;
+    /// use tyr::parser::Parser;
+    ///
+    /// let program = Parser::new().parse("LOADC 1\nend:\nHALT").ok().unwrap();
+    ///
+    /// assert_eq!(program.len(), 3);
+    /// assert_eq!(program.get(2), Some(&OpCode::HALT));
+    /// assert_eq!(program.label("end"), Some(2));
+    /// ```
+    pub fn parse(mut self, source: &str) -> Result<Program, OpError> {
+        let mut code = Vec::new();
+        let mut lines = Vec::new();
+
+        for text in source.lines() {
+            lines.push(self.line);
+            code.push(self.parse_line(text)?);
+        }
+
+        Ok(Program::new(code, self.sym_tab).lines(lines))
+    }
+
     /// parse_line takes in a line as a String, and returns a result
     /// containing an OpCode or OpError, as defined in op.rs. In the
     /// match expression, we assume any non-operation string to be a
     /// label. Label handling is done in the parse_label function.
     ///
-    /// When we're given an empty string, we assume a NOP. This
-    /// function will not panic; that can be decided by the caller
+    /// Lines beginning with a period are directives, which change how the
+    /// vm runs the program. Directives are handled in parse_directive.
+    ///
+    /// The line is split into tokens by token::tokenize, so operands may
+    /// be separated by any whitespace, and a ';' or '#' starts a comment.
+    /// When we're given a line with no tokens, such as an empty line or
+    /// a comment, we assume a NOP. A token left over after the operands
+    /// an operation takes is an OpError::Syntax pointing at that token.
+    /// This function will not panic; that can be decided by the caller
     /// when they handle the Result.
     ///
     /// ## Example
     ///
     /// ```
     /// use tyr::op::OpCode;
-    /// use tyr::sym_tab::SymbolTable;
     /// use tyr::parser::Parser;
     ///
     /// let prog = "PRINT Hello!".to_string();
-    /// let mut sym_tab = SymbolTable::new();
-    /// let mut parser = Parser::new(&mut sym_tab);
+    /// let mut parser = Parser::new();
     ///
     /// let result = parser.parse_line(&prog).ok().unwrap();
     ///
     /// assert_eq!(OpCode::PRINT("Hello!".to_string()), result);
     /// ```
-    pub fn parse_line(&mut self, line: &String) -> ParseResult {
-        let op_vec: Vec<&str> = line.split(' ').collect();
-        if op_vec.is_empty() {
-            return Ok(OpCode::NOP);
-        }
+    pub fn parse_line(&mut self, line: &str) -> ParseResult {
+        let tokens = token::tokenize(line);
+        let op = match tokens.first() {
+            Some(token) => token.text,
+            None => ""
+        };
 
-        let result = match op_vec[0] {
-            "PRINT" => Ok(OpCode::PRINT(op_vec[1].to_string())),
+        let result = match op {
+            "" => Ok(OpCode::NOP),
+            "PRINT" => Ok(OpCode::PRINT(self.parse_text(line, &tokens)?)),
+            "PRINTV" => Ok(OpCode::PRINTV),
+            "PRINTF" => self.parse_template(line, &tokens),
+            "EPRINT" => Ok(OpCode::EPRINT(self.parse_text(line, &tokens)?)),
+            "READ" => Ok(OpCode::READ),
             "HALT" => Ok(OpCode::HALT),
             "NOP" => Ok(OpCode::NOP),
             "ADD" => Ok(OpCode::ADD),
@@ -67,31 +117,79 @@ impl<'s> Parser<'s> {
             "AND" => Ok(OpCode::AND),
             "OR" => Ok(OpCode::OR),
             "NEG" => Ok(OpCode::NEG),
+            "EQ" => Ok(OpCode::EQ),
+            "NE" => Ok(OpCode::NE),
+            "LT" => Ok(OpCode::LT),
+            "LE" => Ok(OpCode::LE),
+            "GT" => Ok(OpCode::GT),
+            "GE" => Ok(OpCode::GE),
+            "FADD" => Ok(OpCode::FADD),
+            "FSUB" => Ok(OpCode::FSUB),
+            "FMUL" => Ok(OpCode::FMUL),
+            "FDIV" => Ok(OpCode::FDIV),
+            "ITOF" => Ok(OpCode::ITOF),
+            "FTOI" => Ok(OpCode::FTOI),
             "LOAD" => Ok(OpCode::LOAD),
             "STORE" => Ok(OpCode::STORE),
             "LOADV" => {
-                let arg = try!(self.extract_arg(&op_vec));
+                let arg = self.extract_arg(&tokens)?;
                 Ok(OpCode::LOADV(arg))
             },
             "STOREV" => {
-                let arg = try!(self.extract_arg(&op_vec));
+                let arg = self.extract_arg(&tokens)?;
                 Ok(OpCode::STOREV(arg))
             },
-            "JMP" => Ok(OpCode::JMP(op_vec[1].to_string())),
-            "JMPZ" => Ok(OpCode::JMPZ(op_vec[1].to_string())),
+            "JMP" => Ok(OpCode::JMP(self.extract_word(&tokens)?)),
+            "JMPZ" => Ok(OpCode::JMPZ(self.extract_word(&tokens)?)),
+            "JMPNZ" => Ok(OpCode::JMPNZ(self.extract_word(&tokens)?)),
+            "JEQ" => Ok(OpCode::JEQ(self.extract_word(&tokens)?)),
+            "JNE" => Ok(OpCode::JNE(self.extract_word(&tokens)?)),
+            "JLT" => Ok(OpCode::JLT(self.extract_word(&tokens)?)),
+            "JLE" => Ok(OpCode::JLE(self.extract_word(&tokens)?)),
+            "JGT" => Ok(OpCode::JGT(self.extract_word(&tokens)?)),
+            "JGE" => Ok(OpCode::JGE(self.extract_word(&tokens)?)),
             "LOADC" => {
-                let arg = try!(self.extract_arg(&op_vec));
+                let arg = self.extract_arg(&tokens)?;
                 Ok(OpCode::LOADC(arg))
             },
+            "LOADF" => {
+                let arg = self.extract_float_arg(&tokens)?;
+                Ok(OpCode::LOADF(arg))
+            },
             "JMPI" => {
-                let arg = try!(self.extract_arg(&op_vec));
+                let arg = self.extract_arg(&tokens)?;
                 Ok(OpCode::JMPI(arg))
             },
+            "CALL" => Ok(OpCode::CALL(self.extract_word(&tokens)?)),
+            "CALLN" => Ok(OpCode::CALLN(self.extract_word(&tokens)?)),
+            "RET" => Ok(OpCode::RET),
+            "TRAP" => Ok(OpCode::TRAP(self.extract_word(&tokens)?)),
+            "ALLOC" => {
+                let arg = self.extract_arg(&tokens)?;
+                Ok(OpCode::ALLOC(arg))
+            },
+            "FREE" => Ok(OpCode::FREE),
+            "LOADH" => Ok(OpCode::LOADH),
+            "STOREH" => Ok(OpCode::STOREH),
             "DUP" => Ok(OpCode::DUP),
-            _ => self.parse_label(&op_vec)
+            "SWAP" => Ok(OpCode::SWAP),
+            "DROP" => Ok(OpCode::DROP),
+            "OVER" => Ok(OpCode::OVER),
+            "ROT" => Ok(OpCode::ROT),
+            "PICK" => {
+                let arg = self.extract_arg(&tokens)?;
+                Ok(OpCode::PICK(arg))
+            },
+            "DEPTH" => Ok(OpCode::DEPTH),
+            directive if directive.starts_with('.') => self.parse_directive(&tokens),
+            _ => self.parse_label(&tokens)
         };
+        let result = result.and_then(|op| match operand_count(&op) {
+            Some(count) => self.expect_end(&tokens, count + 1).map(|_| op),
+            None => Ok(op)
+        });
 
-        self.line = self.line + 1;
+        self.line += 1;
         result
     }
 
@@ -101,66 +199,244 @@ impl<'s> Parser<'s> {
     /// future use by the vm.
     ///
     /// An Error will be returned if the provided label does not end
-    /// with a colon, or if the label has already been declared
-    /// (that is, the label already exists in the symbol table).
-    fn parse_label(&mut self, op_vec: &Vec<&str>) -> ParseResult {
+    /// with a colon, if the label has already been declared
+    /// (that is, the label already exists in the symbol table), or
+    /// if anything other than a comment follows it.
+    fn parse_label(&mut self, tokens: &[Token]) -> ParseResult {
+        let label = tokens[0].text;
         let result: Result<OpCode, OpError>;
-        let label = op_vec[0];
-        let last_char = label.chars().nth(label.len() - 1).unwrap();
-
-        if last_char != ':' {
-            result = Err(OpError::Label(
-                "tyr: Illegal label name - labels must end with a colon.".to_string()
-            ));
+        let jmp_label = match label.strip_suffix(':') {
+            Some(name) => name,
+            None => {
+                result = Err(OpError::Label(
+                    "tyr: Illegal label name - labels must end with a colon.".to_string()
+                ));
 
-            return result;
-        }
+                return result;
+            }
+        };
 
-        if self.sym_tab.is_duplicate(&label.to_string()) {
+        self.expect_end(tokens, 1)?;
+        if self.sym_tab.is_duplicate(jmp_label) {
             let error = format!("tyr [{:?}]: Duplicate label {:?} found!", self.line, label);
             result = Err(OpError::Label(error));
 
             return result;
         }
 
-        let jmp_label = label.split_at(label.len()-1).0;
         self.sym_tab.insert(jmp_label.to_string(), self.line);
         result = Ok(OpCode::LABEL(jmp_label.to_string(), self.line));
 
         result
     }
 
-    /// Given a line of a program, split into a vector of strings,
-    /// extract the argument provided in the operation into an i64 value.
-    /// For example, if we pass in a vector like ["LOADC", "1"],
-    /// this function would return Ok(1).
+    /// Parses a directive into the OpCode that applies it. The only
+    /// directive is .overflow, which takes the name of an OverflowMode
+    /// and switches integer arithmetic to that mode from that point on:
     ///
-    /// However, if we pass in something like ["LOADC", "hello"],
-    /// we eould return with an OpError::Parse type.
-    fn extract_arg(&self, op_vec: &Vec<&str>) -> ArgResult {
-        if op_vec.len() < 2 {
-            // TODO: Why panic here instead of returning an OpError?
-            panic!("tyr: Missing arguments for load operation {:?}.", op_vec[0]);
+    /// .overflow checked
+    ///
+    /// An Error will be returned for an unknown directive or argument.
+    fn parse_directive(&self, tokens: &[Token]) -> ParseResult {
+        match tokens[0].text {
+            ".overflow" => {
+                let name = tokens.get(1).map_or("", |token| token.text);
+                match OverflowMode::from_name(name) {
+                    Some(mode) => self.expect_end(tokens, 2).map(|_| OpCode::OVERFLOW(mode)),
+                    None => Err(OpError::Directive(
+                        format!("tyr [{:?}]: Unknown overflow mode {:?}", self.line, name)
+                    ))
+                }
+            },
+            directive => Err(OpError::Directive(
+                format!("tyr [{:?}]: Unknown directive {:?}", self.line, directive)
+            ))
+        }
+    }
+
+    /// Parses the template given to PRINTF, which is the rest of the line
+    /// after the opcode, up to any comment. The template may contain
+    /// spaces, and can be written as a string literal to keep leading or
+    /// trailing spaces, to include a ';' or '#', or to use escapes. Like
+    /// PRINT, a missing template is an error.
+    fn parse_template(&self, line: &str, tokens: &[Token]) -> ParseResult {
+        let source = self.parse_text(line, tokens)?;
+        match Template::parse(&source) {
+            Ok(template) => Ok(OpCode::PRINTF(template)),
+            Err(message) => Err(OpError::Format(format!("tyr [{:?}]: {}", self.line, message)))
+        }
+    }
+
+    /// Parses the text given to PRINT and EPRINT. The text is either a
+    /// string literal, or the rest of the line after the opcode, up to
+    /// any comment. A string literal is wrapped in double quotes, and may
+    /// use the escapes \n, \t, \", \\ and \u{..}, which takes the hex code
+    /// of a unicode character:
+    ///
+    /// PRINT "Hello,\tworld!\u{1F600}"
+    ///
+    /// An Error will be returned if the text is missing, the string literal
+    /// is not terminated, uses an unknown escape, or is followed by
+    /// anything other than a comment.
+    fn parse_text(&self, line: &str, tokens: &[Token]) -> Result<String, OpError> {
+        let first = self.operand(tokens)?;
+        if !first.text.starts_with('"') {
+            let last = tokens[tokens.len() - 1];
+            return Ok(line[first.offset..last.end()].to_string());
+        }
+
+        if let Some(extra) = tokens.get(2) {
+            return Err(self.syntax_error(extra.column, &format!("Unexpected {:?} after string", extra.text)));
+        }
+
+        self.parse_string(first)
+    }
+
+    /// Decodes a string literal token, replacing its escapes with the
+    /// characters they stand for.
+    fn parse_string(&self, token: Token) -> Result<String, OpError> {
+        let chars: Vec<char> = token.text.chars().collect();
+        let mut value = String::new();
+        let mut index = 1;
+
+        while index < chars.len() {
+            let column = token.column + index;
+            match chars[index] {
+                '"' if index + 1 < chars.len() => {
+                    return Err(self.syntax_error(column + 1, "Unexpected text after string"));
+                },
+                '"' => return Ok(value),
+                '\\' => {
+                    index += 1;
+                    match chars.get(index) {
+                        Some('n') => value.push('\n'),
+                        Some('t') => value.push('\t'),
+                        Some('"') => value.push('"'),
+                        Some('\\') => value.push('\\'),
+                        Some('u') => {
+                            let (ch, len) = self.parse_unicode(&chars[index + 1..], column)?;
+                            value.push(ch);
+                            index += len;
+                        },
+                        Some(escape) => {
+                            return Err(self.syntax_error(column, &format!("Unknown escape \\{}", escape)));
+                        },
+                        None => break
+                    }
+                },
+                ch => value.push(ch)
+            }
+            index += 1;
+        }
+
+        Err(self.syntax_error(token.column, "Unterminated string"))
+    }
+
+    /// Decodes the braces of a \u{..} escape, given the characters that
+    /// follow the 'u'. The braces must hold one to six ASCII hex digits,
+    /// with no sign. Returns the character along with the number of
+    /// characters the braces took up.
+    fn parse_unicode(&self, chars: &[char], column: usize) -> Result<(char, usize), OpError> {
+        let close = chars.iter().position(|ch| *ch == '}').unwrap_or(0);
+        let digits = chars.get(1..close).unwrap_or(&[]);
+        if chars.first() == Some(&'{') && (2..=7).contains(&close) && digits.iter().all(|ch| ch.is_ascii_hexdigit()) {
+            let hex: String = digits.iter().collect();
+            if let Some(ch) = u32::from_str_radix(&hex, 16).ok().and_then(::std::char::from_u32) {
+                return Ok((ch, close + 1));
+            }
         }
 
-        let arg = try!(op_vec[1].parse::<i64>());
+        Err(self.syntax_error(column, "Invalid unicode escape"))
+    }
+
+    /// Given the tokens of a line, return the token holding the argument
+    /// of the operation. An OpError::Syntax is returned when the operation
+    /// is missing its argument, pointing at the column where it should be.
+    fn operand<'t>(&self, tokens: &[Token<'t>]) -> Result<Token<'t>, OpError> {
+        match tokens.get(1) {
+            Some(token) => Ok(*token),
+            None => {
+                let op = tokens[0];
+                let column = op.column + op.text.chars().count();
+                Err(self.syntax_error(column, &format!("Missing argument for {}", op.text)))
+            }
+        }
+    }
+
+    /// Checks that a line holds no more than the given number of tokens.
+    /// Otherwise, an OpError::Syntax is returned pointing at the first
+    /// token too many.
+    fn expect_end(&self, tokens: &[Token], count: usize) -> Result<(), OpError> {
+        match tokens.get(count) {
+            Some(extra) => {
+                let taken: Vec<&str> = tokens[..count].iter().map(|token| token.text).collect();
+                Err(self.syntax_error(extra.column, &format!("Unexpected {:?} after {}", extra.text, taken.join(" "))))
+            },
+            None => Ok(())
+        }
+    }
+
+    /// Creates an OpError::Syntax pointing at a column of the current line.
+    fn syntax_error(&self, column: usize, message: &str) -> OpError {
+        OpError::Syntax(format!("tyr [{}:{}]: {}", self.line, column, message))
+    }
+
+    /// Given the tokens of a line, extract the argument provided in the
+    /// operation as a string, such as the label given to JMP.
+    fn extract_word(&self, tokens: &[Token]) -> Result<String, OpError> {
+        Ok(self.operand(tokens)?.text.to_string())
+    }
+
+    /// Given the tokens of a line, extract the argument provided in
+    /// the operation into an i64 value. For example, if we pass in the
+    /// tokens of "LOADC 1", this function would return Ok(1).
+    ///
+    /// However, if we pass in something like "LOADC hello",
+    /// we would return with an OpError::Parse type.
+    fn extract_arg(&self, tokens: &[Token]) -> ArgResult {
+        let arg = self.operand(tokens)?.text.parse::<i64>()?;
+
+        Ok(arg)
+    }
+
+    /// Given the tokens of a line, extract the argument provided in the
+    /// operation into an f64 value. For example, "LOADF 1.5" returns
+    /// Ok(1.5). Besides decimal and exponent notation, "inf", "-inf"
+    /// and "NaN" are accepted.
+    fn extract_float_arg(&self, tokens: &[Token]) -> FloatArgResult {
+        let arg = self.operand(tokens)?.text.parse::<f64>()?;
 
         Ok(arg)
     }
 }
 
+/// Returns the number of operands an instruction is written with, or None
+/// for instructions whose parsing already checks the rest of the line:
+/// the text taken by PRINT, EPRINT and PRINTF, labels and directives.
+fn operand_count(op: &OpCode) -> Option<usize> {
+    match *op {
+        OpCode::PRINT(_) | OpCode::EPRINT(_) | OpCode::PRINTF(_) |
+        OpCode::LABEL(_, _) | OpCode::OVERFLOW(_) => None,
+        OpCode::LOADC(_) | OpCode::LOADF(_) | OpCode::LOADV(_) | OpCode::STOREV(_) |
+        OpCode::JMP(_) | OpCode::JMPZ(_) | OpCode::JMPNZ(_) | OpCode::JEQ(_) |
+        OpCode::JNE(_) | OpCode::JLT(_) | OpCode::JLE(_) | OpCode::JGT(_) |
+        OpCode::JGE(_) | OpCode::JMPI(_) | OpCode::CALL(_) | OpCode::CALLN(_) |
+        OpCode::TRAP(_) | OpCode::ALLOC(_) | OpCode::PICK(_) => Some(1),
+        _ => Some(0)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use op::{OpCode, OpError};
-    use sym_tab::SymbolTable;
+    use format::Template;
 
     #[test]
     fn parse_line_print() {
         let prog = "PRINT test".to_string();
         let expected = OpCode::PRINT("test".to_string());
-        let mut sym_tab = SymbolTable::new();
-        let mut parser = Parser::new(&mut sym_tab);
+        let mut parser = Parser::new();
 
         let result = parser.parse_line(&prog).ok().unwrap();
 
@@ -171,8 +447,7 @@ mod tests {
     fn parse_line_halt() {
         let prog = "HALT".to_string();
         let expected = OpCode::HALT;
-        let mut sym_tab = SymbolTable::new();
-        let mut parser = Parser::new(&mut sym_tab);
+        let mut parser = Parser::new();
 
         let result = parser.parse_line(&prog).ok().unwrap();
 
@@ -183,8 +458,7 @@ mod tests {
     fn parse_line_nop() {
         let prog = "NOP".to_string();
         let expected = OpCode::NOP;
-        let mut sym_tab = SymbolTable::new();
-        let mut parser = Parser::new(&mut sym_tab);
+        let mut parser = Parser::new();
 
         let result = parser.parse_line(&prog).ok().unwrap();
 
@@ -195,8 +469,7 @@ mod tests {
     fn parse_line_add() {
         let prog = "ADD".to_string();
         let expected = OpCode::ADD;
-        let mut sym_tab = SymbolTable::new();
-        let mut parser = Parser::new(&mut sym_tab);
+        let mut parser = Parser::new();
 
         let result = parser.parse_line(&prog).ok().unwrap();
 
@@ -207,8 +480,50 @@ mod tests {
     fn parse_line_loadc() {
         let prog = "LOADC 5".to_string();
         let expected = OpCode::LOADC(5);
-        let mut sym_tab = SymbolTable::new();
-        let mut parser = Parser::new(&mut sym_tab);
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_loadf() {
+        let prog = "LOADF -2.5".to_string();
+        let expected = OpCode::LOADF(-2.5);
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_loadf_exponent() {
+        let prog = "LOADF 1e3".to_string();
+        let expected = OpCode::LOADF(1000.0);
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_loadf_illegal_arg() {
+        let prog = "LOADF one".to_string();
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog);
+        // Parse should fail when trying to parse "one" as an f64.
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn parse_line_fadd() {
+        let prog = "FADD".to_string();
+        let expected = OpCode::FADD;
+        let mut parser = Parser::new();
 
         let result = parser.parse_line(&prog).ok().unwrap();
 
@@ -219,8 +534,7 @@ mod tests {
     fn parse_line_label() {
         let prog = "testlabel:".to_string();
         let expected = OpCode::LABEL("testlabel".to_string(), 1);
-        let mut sym_tab = SymbolTable::new();
-        let mut parser = Parser::new(&mut sym_tab);
+        let mut parser = Parser::new();
 
         let result = parser.parse_line(&prog).ok().unwrap();
 
@@ -233,42 +547,61 @@ mod tests {
         let expected = OpError::Label(
              "tyr: Illegal label name - labels must end with a colon.".to_string()
         );
-        let mut sym_tab = SymbolTable::new();
-        let mut parser = Parser::new(&mut sym_tab);
+        let mut parser = Parser::new();
 
         let result = parser.parse_line(&prog).err().unwrap();
 
         assert_eq!(expected, result);
     }
 
+    #[test]
+    fn parse_line_label_non_ascii() {
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line("café:").ok().unwrap();
+        assert_eq!(OpCode::LABEL("café".to_string(), 1), result);
+
+        let expected = OpError::Label(
+             "tyr: Illegal label name - labels must end with a colon.".to_string()
+        );
+        let result = parser.parse_line("é").err().unwrap();
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_duplicate_label() {
+        let expected = OpError::Label("tyr [2]: Duplicate label \"a:\" found!".to_string());
+
+        let result = Parser::new().parse("a:\na:").err().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
     #[test]
     fn parse_line_illegal_op() {
         let prog = "TEST".to_string();
-        let mut sym_tab = SymbolTable::new();
-        let mut parser = Parser::new(&mut sym_tab);
+        let mut parser = Parser::new();
 
         let result = parser.parse_line(&prog);
         // Parse should fail when trying to parse the operation as a label.
-        assert_eq!(result.is_ok(), false);
+        assert!(result.is_err());
     }
 
     #[test]
     fn parse_line_illegal_arg() {
         let prog = "LOADC h".to_string();
-        let mut sym_tab = SymbolTable::new();
-        let mut parser = Parser::new(&mut sym_tab);
+        let mut parser = Parser::new();
 
         let result = parser.parse_line(&prog);
         // Parse should fail when trying to parse "h" as an i64.
-        assert_eq!(result.is_ok(), false);
+        assert!(result.is_err());
     }
 
     #[test]
     fn parse_line_loadv() {
         let prog = "LOADV 5".to_string();
         let expected = OpCode::LOADV(5);
-        let mut sym_tab = SymbolTable::new();
-        let mut parser = Parser::new(&mut sym_tab);
+        let mut parser = Parser::new();
 
         let result = parser.parse_line(&prog).ok().unwrap();
 
@@ -279,8 +612,7 @@ mod tests {
     fn parse_line_storev() {
         let prog = "STOREV 5".to_string();
         let expected = OpCode::STOREV(5);
-        let mut sym_tab = SymbolTable::new();
-        let mut parser = Parser::new(&mut sym_tab);
+        let mut parser = Parser::new();
 
         let result = parser.parse_line(&prog).ok().unwrap();
 
@@ -291,8 +623,73 @@ mod tests {
     fn parse_line_jmpi() {
         let prog = "JMPI 5".to_string();
         let expected = OpCode::JMPI(5);
-        let mut sym_tab = SymbolTable::new();
-        let mut parser = Parser::new(&mut sym_tab);
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_printv() {
+        let prog = "PRINTV".to_string();
+        let expected = OpCode::PRINTV;
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_printf() {
+        let prog = "PRINTF \"x={} y={:x} \"".to_string();
+        let expected = OpCode::PRINTF(Template::parse("x={} y={:x} ").unwrap());
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_printf_unquoted() {
+        let prog = "PRINTF sum: {:04}".to_string();
+        let expected = OpCode::PRINTF(Template::parse("sum: {:04}").unwrap());
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_printf_malformed() {
+        let prog = "PRINTF x={".to_string();
+        let mut parser = Parser::new();
+
+        match parser.parse_line(&prog) {
+            Err(OpError::Format(_)) => {},
+            result => panic!("expected format error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn parse_line_eprint() {
+        let prog = "EPRINT oops".to_string();
+        let expected = OpCode::EPRINT("oops".to_string());
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_read() {
+        let prog = "READ".to_string();
+        let expected = OpCode::READ;
+        let mut parser = Parser::new();
 
         let result = parser.parse_line(&prog).ok().unwrap();
 
@@ -303,8 +700,351 @@ mod tests {
     fn parse_line_dup() {
         let prog = "DUP".to_string();
         let expected = OpCode::DUP;
-        let mut sym_tab = SymbolTable::new();
-        let mut parser = Parser::new(&mut sym_tab);
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_swap() {
+        let prog = "SWAP".to_string();
+        let expected = OpCode::SWAP;
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_pick() {
+        let prog = "PICK 2".to_string();
+        let expected = OpCode::PICK(2);
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_depth() {
+        let prog = "DEPTH".to_string();
+        let expected = OpCode::DEPTH;
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_lt() {
+        let prog = "LT".to_string();
+        let expected = OpCode::LT;
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_jge() {
+        let prog = "JGE loop".to_string();
+        let expected = OpCode::JGE("loop".to_string());
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_overflow_directive() {
+        let prog = ".overflow saturating".to_string();
+        let expected = OpCode::OVERFLOW(OverflowMode::Saturating);
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_overflow_directive_unknown_mode() {
+        let prog = ".overflow sometimes".to_string();
+        let expected = OpError::Directive("tyr [1]: Unknown overflow mode \"sometimes\"".to_string());
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).err().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_unknown_directive() {
+        let prog = ".stack 10".to_string();
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn parse_line_call() {
+        let prog = "CALL routine".to_string();
+        let expected = OpCode::CALL("routine".to_string());
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_alloc() {
+        let prog = "ALLOC 4".to_string();
+        let expected = OpCode::ALLOC(4);
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_storeh() {
+        let prog = "STOREH".to_string();
+        let expected = OpCode::STOREH;
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_calln() {
+        let prog = "CALLN lookup".to_string();
+        let expected = OpCode::CALLN("lookup".to_string());
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_trap() {
+        let prog = "TRAP handler".to_string();
+        let expected = OpCode::TRAP("handler".to_string());
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_ret() {
+        let prog = "RET".to_string();
+        let expected = OpCode::RET;
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_program() {
+        let source = "LOADC 2\nloop:\nJMP loop";
+        let program = Parser::new().parse(source).ok().unwrap();
+
+        assert_eq!(program.code(), &[
+            OpCode::LOADC(2),
+            OpCode::LABEL("loop".to_string(), 2),
+            OpCode::JMP("loop".to_string())
+        ][..]);
+        assert_eq!(program.label("loop"), Some(2));
+        assert_eq!(program.line(2), Some(3));
+    }
+
+    #[test]
+    fn parse_program_error() {
+        let result = Parser::new().parse("LOADC 1\nLOADC x\nHALT");
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn parse_line_whitespace() {
+        let prog = "\tLOADC   5  ".to_string();
+        let expected = OpCode::LOADC(5);
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_trailing_comment() {
+        let prog = "JMP loop ; go around again".to_string();
+        let expected = OpCode::JMP("loop".to_string());
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_comment() {
+        let mut parser = Parser::new();
+
+        assert_eq!(parser.parse_line("# setup").ok().unwrap(), OpCode::NOP);
+        assert_eq!(parser.parse_line("   ").ok().unwrap(), OpCode::NOP);
+        assert_eq!(parser.parse_line("").ok().unwrap(), OpCode::NOP);
+        assert_eq!(parser.parse_line("end: ; done").ok().unwrap(), OpCode::LABEL("end".to_string(), 4));
+    }
+
+    #[test]
+    fn parse_line_printf_comment() {
+        let prog = "PRINTF \"a; {}\"  # show a".to_string();
+        let expected = OpCode::PRINTF(Template::parse("a; {}").unwrap());
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_missing_arg() {
+        let prog = "  LOADC ; nothing".to_string();
+        let expected = OpError::Syntax("tyr [1:8]: Missing argument for LOADC".to_string());
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).err().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_print_string() {
+        let prog = r#"PRINT "Hello, world!\n""#.to_string();
+        let expected = OpCode::PRINT("Hello, world!\n".to_string());
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_print_escapes() {
+        let prog = r#"EPRINT "\t\"a\\b\" \u{e9}\u{1F600} ; #" ; done"#.to_string();
+        let expected = OpCode::EPRINT("\t\"a\\b\" \u{e9}\u{1F600} ; #".to_string());
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_display_round_trip() {
+        let lines = [
+            "PRINT say \"hi\"\t\\ ok",
+            "EPRINT \"a\\u{1}b\"",
+            "PRINTF \"{{x}}={:04x} \"",
+            "LOADC -5",
+            "LOADF 2.5",
+            "LOADF inf",
+            "JGE loop",
+            "CALLN clock",
+            "loop:",
+            ".overflow checked",
+            "ROT"
+        ];
+
+        for line in lines.iter() {
+            let op = Parser::new().parse_line(line).ok().unwrap();
+            let written = op.to_string();
+
+            assert_eq!(Parser::new().parse_line(&written), Ok(op), "{}", written);
+        }
+        assert_eq!(Parser::new().parse_line("LOADC  7 ; seven").unwrap().to_string(), "LOADC 7");
+    }
+
+    #[test]
+    fn parse_line_print_words() {
+        let prog = "PRINT Hello  world ; greet".to_string();
+        let expected = OpCode::PRINT("Hello  world".to_string());
+        let mut parser = Parser::new();
+
+        let result = parser.parse_line(&prog).ok().unwrap();
+
+        assert_eq!(expected, result);
+    }
+
+    #[test]
+    fn parse_line_print_errors() {
+        let errors = vec![
+            ("PRINT", "tyr [1:6]: Missing argument for PRINT"),
+            ("EPRINT", "tyr [1:7]: Missing argument for EPRINT"),
+            ("PRINTF", "tyr [1:7]: Missing argument for PRINTF"),
+            (r#"PRINT "Hello"#, "tyr [1:7]: Unterminated string"),
+            (r#"PRINT "Hello\""#, "tyr [1:7]: Unterminated string"),
+            (r#"PRINT "a\qb""#, "tyr [1:9]: Unknown escape \\q"),
+            (r#"PRINT "\u{110000}""#, "tyr [1:8]: Invalid unicode escape"),
+            (r#"PRINT "\u{}""#, "tyr [1:8]: Invalid unicode escape"),
+            (r#"PRINT "\u{+41}""#, "tyr [1:8]: Invalid unicode escape"),
+            (r#"PRINT "\u{-1}""#, "tyr [1:8]: Invalid unicode escape"),
+            (r#"PRINT "a"b"#, "tyr [1:10]: Unexpected text after string"),
+            (r#"PRINT "a" b"#, "tyr [1:11]: Unexpected \"b\" after string")
+        ];
+
+        for (prog, message) in errors {
+            let mut parser = Parser::new();
+            let result = parser.parse_line(prog).err().unwrap();
+
+            assert_eq!(result, OpError::Syntax(message.to_string()), "parsing {}", prog);
+        }
+    }
+
+    #[test]
+    fn parse_line_extra_tokens() {
+        let errors = vec![
+            ("ADD 3", "tyr [1:5]: Unexpected \"3\" after ADD"),
+            ("PRINTV x", "tyr [1:8]: Unexpected \"x\" after PRINTV"),
+            ("LOADC 5 6", "tyr [1:9]: Unexpected \"6\" after LOADC 5"),
+            ("LOADF 1.5\t2", "tyr [1:11]: Unexpected \"2\" after LOADF 1.5"),
+            ("JMP a b ; jump", "tyr [1:7]: Unexpected \"b\" after JMP a"),
+            ("CALLN clock now", "tyr [1:13]: Unexpected \"now\" after CALLN clock"),
+            ("end: junk", "tyr [1:6]: Unexpected \"junk\" after end:"),
+            (".overflow checked twice", "tyr [1:19]: Unexpected \"twice\" after .overflow checked")
+        ];
+
+        for (prog, message) in errors {
+            let mut parser = Parser::new();
+            let result = parser.parse_line(prog).err().unwrap();
+
+            assert_eq!(result, OpError::Syntax(message.to_string()), "parsing {}", prog);
+            assert_eq!(parser.sym_tab.get("end"), None);
+        }
+    }
+
+    #[test]
+    fn parse_line_printf_escapes() {
+        let prog = r#"PRINTF "\"{}\"\t{:x}""#.to_string();
+        let expected = OpCode::PRINTF(Template::parse("\"{}\"\t{:x}").unwrap());
+        let mut parser = Parser::new();
 
         let result = parser.parse_line(&prog).ok().unwrap();
 
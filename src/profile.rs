@@ -0,0 +1,218 @@
+use op::OpCode;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use sym_tab::SymbolTable;
+
+/// Name given to instructions that come before the first label.
+const TOP_LEVEL: &str = "main";
+
+/// Profiler counts the instructions executed by the vm. Counts are kept
+/// per instruction address, per enclosing label and per kind of opcode.
+/// The enclosing label of an instruction is the closest label before it
+/// in the program.
+///
+/// Along with the counts, the profiler keeps the chain of subroutine
+/// calls that led to each instruction, which it writes out as folded
+/// stacks for flamegraph tools.
+pub struct Profiler {
+    /// Labels sorted by address.
+    labels: Vec<(usize, String)>,
+    /// Executions and mnemonic of each instruction, keyed by address.
+    pcs: BTreeMap<usize, (u64, &'static str)>,
+    /// Executions of each kind of opcode.
+    ops: BTreeMap<&'static str, u64>,
+    /// Executions under each chain of subroutine calls, keyed by the
+    /// frame of each call, outermost first. See frame_of for how frames
+    /// are numbered.
+    stacks: BTreeMap<Vec<usize>, u64>,
+    /// Frames of the instruction being recorded, reused between records
+    /// so that counting an existing chain does not allocate.
+    frames: Vec<usize>
+}
+
+impl Profiler {
+    pub fn new(sym_tab: &SymbolTable) -> Profiler {
+        let mut labels: Vec<(usize, String)> = sym_tab.iter()
+            .map(|(label, addr)| (*addr, label.clone()))
+            .collect();
+        labels.sort();
+
+        Profiler {
+            labels,
+            pcs: BTreeMap::new(),
+            ops: BTreeMap::new(),
+            stacks: BTreeMap::new(),
+            frames: Vec::new()
+        }
+    }
+
+    /// Counts one execution of an instruction. The call stack holds the
+    /// return addresses of the subroutines being executed, outermost first.
+    pub fn record(&mut self, pc: usize, op: &OpCode, call_stack: &[usize]) {
+        let name = op.name();
+        self.pcs.entry(pc).or_insert((0, name)).0 += 1;
+        *self.ops.entry(name).or_insert(0) += 1;
+
+        let labels = &self.labels;
+        self.frames.clear();
+        self.frames.extend(call_stack.iter().map(|ret| frame_of(labels, ret.saturating_sub(1))));
+        self.frames.push(frame_of(labels, pc));
+
+        match self.stacks.get_mut(&self.frames[..]) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(self.frames.clone(), 1);
+            }
+        }
+    }
+
+    /// Returns the number of times the instruction at an address ran.
+    pub fn pc_count(&self, pc: usize) -> u64 {
+        self.pcs.get(&pc).map_or(0, |entry| entry.0)
+    }
+
+    /// Returns the number of instructions executed under each label.
+    pub fn label_counts(&self) -> BTreeMap<&str, u64> {
+        let mut counts = BTreeMap::new();
+        for (pc, entry) in &self.pcs {
+            *counts.entry(self.label_of(*pc)).or_insert(0) += entry.0;
+        }
+
+        counts
+    }
+
+    /// Returns the number of times each kind of opcode ran.
+    pub fn op_counts(&self) -> &BTreeMap<&'static str, u64> {
+        &self.ops
+    }
+
+    /// Writes the counts as a text report, with each section sorted from
+    /// the most executed entry to the least.
+    pub fn report(&self) -> String {
+        let total: u64 = self.ops.values().sum();
+        let mut out = String::new();
+
+        let _ = writeln!(out, "Instructions executed: {}", total);
+
+        let _ = writeln!(out, "\nBy address:");
+        let pcs = sorted(self.pcs.iter().map(|(pc, entry)| ((*pc, entry.1), entry.0)));
+        for ((pc, name), count) in pcs {
+            let _ = writeln!(out, "{:>12}  {:>6.2}%  {:>5}  {}", count, percent(count, total), pc, name);
+        }
+
+        let _ = writeln!(out, "\nBy label:");
+        for (label, count) in sorted(self.label_counts().into_iter()) {
+            let _ = writeln!(out, "{:>12}  {:>6.2}%  {}", count, percent(count, total), label);
+        }
+
+        let _ = writeln!(out, "\nBy opcode:");
+        for (name, count) in sorted(self.ops.iter().map(|(name, count)| (*name, *count))) {
+            let _ = writeln!(out, "{:>12}  {:>6.2}%  {}", count, percent(count, total), name);
+        }
+
+        out
+    }
+
+    /// Writes the call chains in the folded stack format read by
+    /// flamegraph tools, one "outer;inner count" line per chain.
+    pub fn folded(&self) -> String {
+        let mut out = String::new();
+        for (frames, count) in &self.stacks {
+            let names: Vec<&str> = frames.iter().map(|frame| self.frame_name(*frame)).collect();
+            let _ = writeln!(out, "{} {}", names.join(";"), count);
+        }
+
+        out
+    }
+
+    /// Returns the name of the closest label at or before an address.
+    fn label_of(&self, pc: usize) -> &str {
+        self.frame_name(frame_of(&self.labels, pc))
+    }
+
+    /// Returns the name of the label a frame stands for.
+    fn frame_name(&self, frame: usize) -> &str {
+        match frame {
+            0 => TOP_LEVEL,
+            frame => &self.labels[frame - 1].1
+        }
+    }
+}
+
+/// Returns the frame of the closest label at or before an address: 0 for
+/// instructions before the first label, and otherwise one more than the
+/// label's index in the sorted labels.
+fn frame_of(labels: &[(usize, String)], pc: usize) -> usize {
+    match labels.binary_search_by(|&(addr, _)| addr.cmp(&pc)) {
+        Ok(index) => index + 1,
+        Err(index) => index
+    }
+}
+
+/// Sorts entries from the highest count to the lowest, keeping the
+/// order of the keys for equal counts.
+fn sorted<K: Ord, I: Iterator<Item = (K, u64)>>(entries: I) -> Vec<(K, u64)> {
+    let mut entries: Vec<(K, u64)> = entries.collect();
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    entries
+}
+
+fn percent(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 * 100.0 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profiler() -> Profiler {
+        let mut sym_tab = SymbolTable::new();
+        sym_tab.insert("square".to_string(), 3);
+
+        Profiler::new(&sym_tab)
+    }
+
+    #[test]
+    fn test_record_counts() {
+        let mut profiler = profiler();
+        profiler.record(0, &OpCode::LOADC(1), &[]);
+        profiler.record(3, &OpCode::DUP, &[2]);
+        profiler.record(4, &OpCode::MUL, &[2]);
+        profiler.record(0, &OpCode::LOADC(1), &[]);
+
+        assert_eq!(profiler.pc_count(0), 2);
+        assert_eq!(profiler.pc_count(1), 0);
+        assert_eq!(profiler.label_counts().get("main"), Some(&2));
+        assert_eq!(profiler.label_counts().get("square"), Some(&2));
+        assert_eq!(profiler.op_counts().get("LOADC"), Some(&2));
+    }
+
+    #[test]
+    fn test_folded() {
+        let mut profiler = profiler();
+        profiler.record(1, &OpCode::CALL("square".to_string()), &[]);
+        profiler.record(3, &OpCode::DUP, &[2]);
+        profiler.record(4, &OpCode::MUL, &[2]);
+
+        assert_eq!(profiler.folded(), "main 1\nmain;square 2\n");
+    }
+
+    #[test]
+    fn test_report_sorted() {
+        let mut profiler = profiler();
+        profiler.record(0, &OpCode::NOP, &[]);
+        profiler.record(3, &OpCode::DUP, &[]);
+        profiler.record(3, &OpCode::DUP, &[]);
+
+        let report = profiler.report();
+        let dup = report.find("DUP").unwrap();
+        let nop = report.find("NOP").unwrap();
+
+        assert!(report.starts_with("Instructions executed: 3\n"));
+        assert!(dup < nop);
+    }
+}
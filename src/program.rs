@@ -0,0 +1,133 @@
+use op::OpCode;
+use sym_tab::SymbolTable;
+
+/// Program holds everything the vm needs to run a parsed tyr program:
+/// the instructions, the table of labels they jump to, the source line
+/// each instruction came from, and the address execution starts at.
+///
+/// A Program owns all of its parts, so it can be kept around after
+/// parsing, and shared between threads by wrapping it in an Arc.
+///
+/// ## Example
+///
+/// ```
+/// use tyr::op::OpCode;
+/// use tyr::program::Program;
+/// use tyr::sym_tab::SymbolTable;
+///
+/// let mut sym_tab = SymbolTable::new();
+/// sym_tab.insert("start".to_string(), 1);
+///
+/// let code = vec![OpCode::HALT, OpCode::LOADC(1)];
+/// let program = Program::new(code, sym_tab).entry(1);
+///
+/// assert_eq!(program.get_entry(), 1);
+/// assert_eq!(program.label("start"), Some(1));
+/// assert_eq!(program.line(1), Some(2));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    /// Instructions, indexed by address.
+    code: Vec<OpCode>,
+    /// Addresses of the labels declared in the program.
+    sym_tab: SymbolTable,
+    /// Source line of each instruction, indexed by address.
+    lines: Vec<usize>,
+    /// Address of the first instruction to execute.
+    entry: usize
+}
+
+impl Program {
+    /// Creates a program that starts at its first instruction. Each
+    /// instruction is taken to come from its own line of source, so the
+    /// instruction at address n is on line n + 1.
+    pub fn new(code: Vec<OpCode>, sym_tab: SymbolTable) -> Program {
+        let lines = (1..=code.len()).collect();
+
+        Program {
+            code,
+            sym_tab,
+            lines,
+            entry: 0
+        }
+    }
+
+    /// Sets the source line of each instruction, in address order.
+    ///
+    /// Panics if there is not exactly one line per instruction.
+    pub fn lines(mut self, lines: Vec<usize>) -> Program {
+        assert_eq!(lines.len(), self.code.len(), "tyr: Expected one source line per instruction");
+        self.lines = lines;
+        self
+    }
+
+    /// Sets the address of the first instruction to execute.
+    pub fn entry(mut self, entry: usize) -> Program {
+        self.entry = entry;
+        self
+    }
+
+    pub fn get_entry(&self) -> usize {
+        self.entry
+    }
+
+    /// Returns the instructions of the program, indexed by address.
+    pub fn code(&self) -> &[OpCode] {
+        &self.code
+    }
+
+    pub fn sym_tab(&self) -> &SymbolTable {
+        &self.sym_tab
+    }
+
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
+    /// Returns the instruction at an address, or None if the address is
+    /// past the end of the program.
+    pub fn get(&self, addr: usize) -> Option<&OpCode> {
+        self.code.get(addr)
+    }
+
+    /// Returns the address a label points at.
+    pub fn label(&self, name: &str) -> Option<usize> {
+        self.sym_tab.get(name).cloned()
+    }
+
+    /// Returns the source line of the instruction at an address.
+    pub fn line(&self, addr: usize) -> Option<usize> {
+        self.lines.get(addr).cloned()
+    }
+
+    /// Returns the address of the first instruction on a source line.
+    pub fn addr_of_line(&self, line: usize) -> Option<usize> {
+        self.lines.iter().position(|l| *l == line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_lines() {
+        let code = vec![OpCode::LOADC(1), OpCode::LOADC(2), OpCode::ADD];
+        let program = Program::new(code, SymbolTable::new()).lines(vec![2, 4, 5]);
+
+        assert_eq!(program.line(1), Some(4));
+        assert_eq!(program.line(3), None);
+        assert_eq!(program.addr_of_line(5), Some(2));
+        assert_eq!(program.addr_of_line(3), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_lines_length_mismatch() {
+        Program::new(vec![OpCode::NOP], SymbolTable::new()).lines(vec![1, 2]);
+    }
+}
@@ -0,0 +1,444 @@
+use config::OverflowMode;
+use op::OpCode;
+use std::fmt;
+use value::Value;
+
+/// Bytes every snapshot starts with.
+pub const MAGIC: &[u8; 4] = b"TYRS";
+
+/// Version of the snapshot format written by this build. Snapshots
+/// written with any other version are refused.
+pub const VERSION: u16 = 2;
+
+/// SnapshotErrors are returned when a snapshot cannot be restored.
+///
+/// InvalidFormat: The data is not a tyr snapshot, or is corrupted.
+/// UnsupportedVersion: The snapshot was written in another format version.
+/// ProgramMismatch: The snapshot was taken from a vm running another program.
+/// Truncated: The data ends before the snapshot does.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SnapshotError {
+    InvalidFormat(String),
+    UnsupportedVersion(u16),
+    ProgramMismatch,
+    Truncated
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SnapshotError::InvalidFormat(ref reason) => write!(f, "Invalid snapshot: {}", reason),
+            SnapshotError::UnsupportedVersion(version) => write!(f, "Unsupported snapshot version {}", version),
+            SnapshotError::ProgramMismatch => write!(f, "Snapshot was taken from a different program"),
+            SnapshotError::Truncated => write!(f, "Snapshot ends unexpectedly")
+        }
+    }
+}
+
+pub type SnapshotResult<T> = Result<T, SnapshotError>;
+
+/// Computes a fingerprint of a program, so that a snapshot can only be
+/// restored into a vm running the same code. This is the 64-bit FNV-1a
+/// hash of the encoded instructions, which does not change between
+/// machines or builds.
+pub fn fingerprint(prog: &[OpCode]) -> u64 {
+    let mut out = Writer::new();
+    for op in prog {
+        encode(&mut out, op);
+    }
+
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in out.into_bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    hash
+}
+
+/// Writes an instruction as a tag byte identifying the opcode, followed
+/// by its operands. Text is written as a length and its UTF-8 bytes, and
+/// floats by their bit pattern, so every instruction has exactly one
+/// encoding. The tags must not change, or fingerprints will.
+fn encode(out: &mut Writer, op: &OpCode) {
+    match *op {
+        OpCode::PRINT(ref text) => {
+            out.u8(0);
+            out.string(text);
+        },
+        OpCode::PRINTV => out.u8(1),
+        OpCode::PRINTF(ref template) => {
+            out.u8(2);
+            out.string(&template.to_string());
+        },
+        OpCode::EPRINT(ref text) => {
+            out.u8(3);
+            out.string(text);
+        },
+        OpCode::READ => out.u8(4),
+        OpCode::LOADC(val) => {
+            out.u8(5);
+            out.u64(val as u64);
+        },
+        OpCode::LOADF(val) => {
+            out.u8(6);
+            out.u64(val.to_bits());
+        },
+        OpCode::LOADV(val) => {
+            out.u8(7);
+            out.u64(val as u64);
+        },
+        OpCode::LABEL(ref label, addr) => {
+            out.u8(8);
+            out.string(label);
+            out.usize(addr);
+        },
+        OpCode::LOAD => out.u8(9),
+        OpCode::STORE => out.u8(10),
+        OpCode::STOREV(val) => {
+            out.u8(11);
+            out.u64(val as u64);
+        },
+        OpCode::ADD => out.u8(12),
+        OpCode::SUB => out.u8(13),
+        OpCode::MUL => out.u8(14),
+        OpCode::DIV => out.u8(15),
+        OpCode::MOD => out.u8(16),
+        OpCode::AND => out.u8(17),
+        OpCode::OR => out.u8(18),
+        OpCode::NEG => out.u8(19),
+        OpCode::EQ => out.u8(20),
+        OpCode::NE => out.u8(21),
+        OpCode::LT => out.u8(22),
+        OpCode::LE => out.u8(23),
+        OpCode::GT => out.u8(24),
+        OpCode::GE => out.u8(25),
+        OpCode::FADD => out.u8(26),
+        OpCode::FSUB => out.u8(27),
+        OpCode::FMUL => out.u8(28),
+        OpCode::FDIV => out.u8(29),
+        OpCode::ITOF => out.u8(30),
+        OpCode::FTOI => out.u8(31),
+        OpCode::HALT => out.u8(32),
+        OpCode::JMP(ref text) => {
+            out.u8(33);
+            out.string(text);
+        },
+        OpCode::JMPZ(ref text) => {
+            out.u8(34);
+            out.string(text);
+        },
+        OpCode::JMPNZ(ref text) => {
+            out.u8(35);
+            out.string(text);
+        },
+        OpCode::JEQ(ref text) => {
+            out.u8(36);
+            out.string(text);
+        },
+        OpCode::JNE(ref text) => {
+            out.u8(37);
+            out.string(text);
+        },
+        OpCode::JLT(ref text) => {
+            out.u8(38);
+            out.string(text);
+        },
+        OpCode::JLE(ref text) => {
+            out.u8(39);
+            out.string(text);
+        },
+        OpCode::JGT(ref text) => {
+            out.u8(40);
+            out.string(text);
+        },
+        OpCode::JGE(ref text) => {
+            out.u8(41);
+            out.string(text);
+        },
+        OpCode::JMPI(val) => {
+            out.u8(42);
+            out.u64(val as u64);
+        },
+        OpCode::CALL(ref text) => {
+            out.u8(43);
+            out.string(text);
+        },
+        OpCode::CALLN(ref text) => {
+            out.u8(44);
+            out.string(text);
+        },
+        OpCode::RET => out.u8(45),
+        OpCode::TRAP(ref text) => {
+            out.u8(46);
+            out.string(text);
+        },
+        OpCode::ALLOC(val) => {
+            out.u8(47);
+            out.u64(val as u64);
+        },
+        OpCode::FREE => out.u8(48),
+        OpCode::LOADH => out.u8(49),
+        OpCode::STOREH => out.u8(50),
+        OpCode::DUP => out.u8(51),
+        OpCode::SWAP => out.u8(52),
+        OpCode::DROP => out.u8(53),
+        OpCode::OVER => out.u8(54),
+        OpCode::ROT => out.u8(55),
+        OpCode::PICK(val) => {
+            out.u8(56);
+            out.u64(val as u64);
+        },
+        OpCode::DEPTH => out.u8(57),
+        OpCode::OVERFLOW(mode) => {
+            out.u8(58);
+            out.u8(match mode {
+                OverflowMode::Wrapping => 0,
+                OverflowMode::Checked => 1,
+                OverflowMode::Saturating => 2
+            });
+        },
+        OpCode::NOP => out.u8(59)
+    }
+}
+
+/// Writes the pieces of a snapshot. All numbers are written in little
+/// endian byte order, and sizes and addresses as 64-bit integers.
+pub struct Writer {
+    buf: Vec<u8>
+}
+
+impl Default for Writer {
+    fn default() -> Writer {
+        Writer::new()
+    }
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer { buf: Vec::new() }
+    }
+
+    pub fn u8(&mut self, val: u8) {
+        self.buf.push(val);
+    }
+
+    pub fn u16(&mut self, val: u16) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, val: u64) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn usize(&mut self, val: usize) {
+        self.u64(val as u64);
+    }
+
+    /// Writes a length followed by the UTF-8 bytes of the text.
+    pub fn string(&mut self, text: &str) {
+        self.usize(text.len());
+        self.buf.extend_from_slice(text.as_bytes());
+    }
+
+    /// Writes an optional number as a flag byte, followed by the number
+    /// when there is one.
+    pub fn option(&mut self, val: Option<u64>) {
+        match val {
+            Some(val) => {
+                self.u8(1);
+                self.u64(val);
+            },
+            None => self.u8(0)
+        }
+    }
+
+    /// Writes a value as a tag byte followed by 8 bytes of data.
+    pub fn value(&mut self, value: Value) {
+        match value {
+            Value::Int(val) => {
+                self.u8(0);
+                self.u64(val as u64);
+            },
+            Value::Bool(val) => {
+                self.u8(1);
+                self.u64(val as u64);
+            },
+            Value::Addr(addr) => {
+                self.u8(2);
+                self.usize(addr);
+            },
+            Value::Float(val) => {
+                self.u8(3);
+                self.u64(val.to_bits());
+            }
+        }
+    }
+
+    /// Writes a length followed by each value.
+    pub fn values(&mut self, values: &[Value]) {
+        self.usize(values.len());
+        for value in values {
+            self.value(*value);
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Reads back the pieces written by a Writer.
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, pos: 0 }
+    }
+
+    fn take(&mut self, count: usize) -> SnapshotResult<&'a [u8]> {
+        if self.bytes.len() - self.pos < count {
+            return Err(SnapshotError::Truncated);
+        }
+
+        let bytes = &self.bytes[self.pos..self.pos + count];
+        self.pos += count;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> SnapshotResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> SnapshotResult<u16> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn u64(&mut self) -> SnapshotResult<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn usize(&mut self) -> SnapshotResult<usize> {
+        let val = self.u64()?;
+        if val > usize::MAX as u64 {
+            return Err(SnapshotError::InvalidFormat(format!("{} does not fit in an address", val)));
+        }
+
+        Ok(val as usize)
+    }
+
+    /// Reads a length, checking that the rest of the data could hold that
+    /// many items of the given size, so a corrupted length cannot cause
+    /// a huge allocation.
+    pub fn count(&mut self, item_size: usize) -> SnapshotResult<usize> {
+        let len = self.usize()?;
+        if len.saturating_mul(item_size) > self.bytes.len() - self.pos {
+            return Err(SnapshotError::Truncated);
+        }
+
+        Ok(len)
+    }
+
+    pub fn option(&mut self) -> SnapshotResult<Option<u64>> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(self.u64()?)),
+            flag => Err(SnapshotError::InvalidFormat(format!("Unknown option flag {}", flag)))
+        }
+    }
+
+    pub fn value(&mut self) -> SnapshotResult<Value> {
+        let tag = self.u8()?;
+        let data = self.u64()?;
+        match tag {
+            0 => Ok(Value::Int(data as i64)),
+            1 => Ok(Value::Bool(data != 0)),
+            2 => Ok(Value::Addr(data as usize)),
+            3 => Ok(Value::Float(f64::from_bits(data))),
+            tag => Err(SnapshotError::InvalidFormat(format!("Unknown value tag {}", tag)))
+        }
+    }
+
+    pub fn values(&mut self) -> SnapshotResult<Vec<Value>> {
+        let len = self.count(9)?;
+        let mut values = Vec::with_capacity(len);
+        for _ in 0..len {
+            values.push(self.value()?);
+        }
+
+        Ok(values)
+    }
+
+    /// Checks that all of the data has been read.
+    pub fn finish(&self) -> SnapshotResult<()> {
+        if self.pos != self.bytes.len() {
+            return Err(SnapshotError::InvalidFormat("Trailing data after snapshot".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values_round_trip() {
+        let values = vec![Value::Int(-7), Value::Bool(true), Value::Addr(12), Value::Float(-0.5)];
+        let mut writer = Writer::new();
+        writer.values(&values);
+        writer.option(Some(3));
+        writer.option(None);
+
+        let bytes = writer.into_bytes();
+        let mut reader = Reader::new(&bytes);
+
+        assert_eq!(reader.values(), Ok(values));
+        assert_eq!(reader.option(), Ok(Some(3)));
+        assert_eq!(reader.option(), Ok(None));
+        assert_eq!(reader.finish(), Ok(()));
+    }
+
+    #[test]
+    fn test_read_truncated() {
+        let mut reader = Reader::new(&[1, 2, 3]);
+
+        assert_eq!(reader.u64(), Err(SnapshotError::Truncated));
+    }
+
+    #[test]
+    fn test_read_huge_length() {
+        let mut writer = Writer::new();
+        writer.u64(u64::MAX);
+        let bytes = writer.into_bytes();
+
+        assert_eq!(Reader::new(&bytes).values(), Err(SnapshotError::Truncated));
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let prog = vec![OpCode::LOADC(1), OpCode::HALT];
+        let other = vec![OpCode::LOADC(2), OpCode::HALT];
+
+        assert_eq!(fingerprint(&prog), fingerprint(&prog.clone()));
+        assert!(fingerprint(&prog) != fingerprint(&other));
+        assert_eq!(fingerprint(&prog), 0x8203_d818_2501_7093);
+    }
+
+    #[test]
+    fn test_fingerprint_operands() {
+        let split = vec![OpCode::PRINT("ab".to_string()), OpCode::PRINT("c".to_string())];
+        let moved = vec![OpCode::PRINT("a".to_string()), OpCode::PRINT("bc".to_string())];
+
+        assert!(fingerprint(&split) != fingerprint(&moved));
+        assert!(fingerprint(&[OpCode::LOADF(0.0)]) != fingerprint(&[OpCode::LOADF(-0.0)]));
+    }
+}
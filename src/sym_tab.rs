@@ -3,11 +3,18 @@ use std::collections::HashMap;
 /// SymbolTable is used to help determine program
 /// addresses to jump to when executing jump
 /// instructions.
+#[derive(Clone, Debug, PartialEq)]
 pub struct SymbolTable {
     /// Hash table mapping a label name to an address in a program.
     table: HashMap<String, usize>
 }
 
+impl Default for SymbolTable {
+    fn default() -> SymbolTable {
+        SymbolTable::new()
+    }
+}
+
 impl SymbolTable {
     pub fn new() -> SymbolTable {
         SymbolTable {
@@ -19,12 +26,17 @@ impl SymbolTable {
         self.table.insert(key, val);
     }
 
-    pub fn get(&self, key: &String) -> Option<&usize> {
+    pub fn get(&self, key: &str) -> Option<&usize> {
         self.table.get(key)
     }
 
-    pub fn is_duplicate(&self, key: &String) -> bool {
-        self.table.get(key).is_some()
+    pub fn is_duplicate(&self, key: &str) -> bool {
+        self.table.contains_key(key)
+    }
+
+    /// Returns every label along with its address, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &usize)> {
+        self.table.iter()
     }
 }
 
@@ -40,6 +52,6 @@ mod tests {
 
         let result = sym_tab.is_duplicate(&key);
 
-        assert_eq!(result, true);
+        assert!(result);
     }
 }
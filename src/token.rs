@@ -0,0 +1,136 @@
+/// Characters that start a comment running to the end of the line.
+const COMMENT_CHARS: [char; 2] = [';', '#'];
+
+/// A word on a line of a tyr program, such as an opcode, an argument
+/// or a label.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Token<'a> {
+    /// The text of the token.
+    pub text: &'a str,
+    /// Column the token starts at, counting characters from 1.
+    pub column: usize,
+    /// Byte offset the token starts at in the line.
+    pub offset: usize
+}
+
+impl<'a> Token<'a> {
+    /// Returns the byte offset just past the end of the token.
+    pub fn end(&self) -> usize {
+        self.offset + self.text.len()
+    }
+}
+
+/// Splits a line of a program into tokens. Tokens are separated by any
+/// amount of whitespace, and a ';' or '#' starts a comment that takes up
+/// the rest of the line. A double quote starts a quoted section that runs
+/// up to the next unescaped double quote, in which whitespace and comment
+/// characters are kept as part of the token.
+///
+/// ## Example
+///
+/// ```
+/// use tyr::token::tokenize;
+///
+/// let tokens = tokenize("  LOADC\t5 ; push five");
+/// let words: Vec<&str> = tokens.iter().map(|token| token.text).collect();
+///
+/// assert_eq!(words, vec!["LOADC", "5"]);
+/// assert_eq!(tokens[1].column, 9);
+/// ```
+pub fn tokenize<'a>(line: &'a str) -> Vec<Token<'a>> {
+    let mut tokens = Vec::new();
+    // Byte offset and column of the token being read, if any.
+    let mut start: Option<(usize, usize)> = None;
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut end = line.len();
+
+    for (column, (offset, ch)) in line.char_indices().enumerate() {
+        if quoted {
+            if escaped {
+                escaped = false;
+            } else if ch == '\\' {
+                escaped = true;
+            } else if ch == '"' {
+                quoted = false;
+            }
+            continue;
+        }
+
+        if ch.is_whitespace() || COMMENT_CHARS.contains(&ch) {
+            if let Some((token_offset, token_column)) = start.take() {
+                tokens.push(Token {
+                    text: &line[token_offset..offset],
+                    column: token_column,
+                    offset: token_offset
+                });
+            }
+
+            if ch.is_whitespace() {
+                continue;
+            }
+
+            end = offset;
+            break;
+        }
+
+        if start.is_none() {
+            start = Some((offset, column + 1));
+        }
+        if ch == '"' {
+            quoted = true;
+        }
+    }
+
+    if let Some((offset, column)) = start {
+        tokens.push(Token {
+            text: &line[offset..end],
+            column,
+            offset
+        });
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(line: &str) -> Vec<&str> {
+        tokenize(line).iter().map(|token| token.text).collect()
+    }
+
+    #[test]
+    fn test_whitespace() {
+        assert_eq!(texts("LOADC 5"), vec!["LOADC", "5"]);
+        assert_eq!(texts("\tLOADC   5  \r"), vec!["LOADC", "5"]);
+        assert!(texts("").is_empty());
+        assert!(texts(" \t ").is_empty());
+    }
+
+    #[test]
+    fn test_comments() {
+        assert!(texts("; a comment").is_empty());
+        assert!(texts("  # a comment").is_empty());
+        assert_eq!(texts("ADD ; add them"), vec!["ADD"]);
+        assert_eq!(texts("JMP loop# again"), vec!["JMP", "loop"]);
+    }
+
+    #[test]
+    fn test_quoted() {
+        assert_eq!(texts("PRINTF \"a ; {}\" # b"), vec!["PRINTF", "\"a ; {}\""]);
+        assert_eq!(texts("PRINT \"say \\\"hi\\\"\""), vec!["PRINT", "\"say \\\"hi\\\"\""]);
+        assert_eq!(texts("PRINT \"open ; end"), vec!["PRINT", "\"open ; end"]);
+    }
+
+    #[test]
+    fn test_columns() {
+        let tokens = tokenize("  JMP\t\tend");
+
+        assert_eq!(tokens[0].column, 3);
+        assert_eq!(tokens[1].column, 8);
+        assert_eq!(tokens[1].offset, 7);
+        assert_eq!(tokens[1].end(), 10);
+    }
+}
@@ -0,0 +1,152 @@
+use op::OpCode;
+use std::io::{self, Write};
+use std::ops::Range;
+use value::Value;
+
+/// A record of one executed instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceRecord<'a> {
+    /// Address of the instruction.
+    pub pc: usize,
+    /// The instruction itself.
+    pub op: &'a OpCode,
+    /// Value on top of the stack before the instruction, if any.
+    pub before: Option<Value>,
+    /// Value on top of the stack after the instruction, if any.
+    pub after: Option<Value>,
+    /// Address jumped to, if the instruction jumped.
+    pub jump: Option<usize>,
+    /// Description of the error, if the instruction failed.
+    pub error: Option<String>
+}
+
+/// Tracer writes a record of each instruction the vm executes, one JSON
+/// object per line. For example, an ADD at address 2 that leaves 3 on
+/// top of the stack is written as:
+///
+/// {"pc":2,"op":"ADD","before":2,"after":3,"jump":null}
+///
+/// The instruction is written the way it appears in the program, so a
+/// LOADC 2 is written as "LOADC 2".
+///
+/// An instruction that fails is still recorded, as the last record of
+/// the run. Its after value is null, and an extra error field holds the
+/// message of the error that stopped the vm.
+///
+/// Integers and finite floats are written as JSON numbers, booleans as
+/// JSON booleans, and anything else, such as addresses, as strings. An
+/// empty stack is written as null.
+pub struct Tracer {
+    out: Box<dyn Write>,
+    range: Option<Range<usize>>
+}
+
+impl Tracer {
+    pub fn new<W: Write + 'static>(out: W) -> Tracer {
+        Tracer {
+            out: Box::new(out),
+            range: None
+        }
+    }
+
+    /// Only records instructions whose address falls inside the range.
+    pub fn range(mut self, range: Range<usize>) -> Tracer {
+        self.range = Some(range);
+        self
+    }
+
+    /// Returns whether an instruction at the given address is recorded.
+    pub fn covers(&self, pc: usize) -> bool {
+        match self.range {
+            Some(ref range) => range.contains(&pc),
+            None => true
+        }
+    }
+
+    /// Writes a record, unless it falls outside of the traced range.
+    pub fn record(&mut self, record: &TraceRecord) -> io::Result<()> {
+        if !self.covers(record.pc) {
+            return Ok(());
+        }
+
+        let jump = match record.jump {
+            Some(addr) => addr.to_string(),
+            None => "null".to_string()
+        };
+
+        let error = match record.error {
+            Some(ref message) => format!(",\"error\":{}", json_string(message)),
+            None => String::new()
+        };
+
+        writeln!(self.out, "{{\"pc\":{},\"op\":{},\"before\":{},\"after\":{},\"jump\":{}{}}}",
+                 record.pc,
+                 json_string(&record.op.to_string()),
+                 json_value(record.before),
+                 json_value(record.after),
+                 jump,
+                 error)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Encodes a stack value as JSON.
+fn json_value(value: Option<Value>) -> String {
+    match value {
+        Some(Value::Int(val)) => val.to_string(),
+        Some(Value::Bool(val)) => val.to_string(),
+        Some(Value::Float(val)) if val.is_finite() => format!("{:?}", val),
+        Some(value) => json_string(&value.to_string()),
+        None => "null".to_string()
+    }
+}
+
+/// Encodes a string as a quoted JSON string.
+fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for ch in text.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => out.push(ch)
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_value() {
+        assert_eq!(json_value(Some(Value::Int(-3))), "-3");
+        assert_eq!(json_value(Some(Value::Bool(true))), "true");
+        assert_eq!(json_value(Some(Value::Float(1.0))), "1.0");
+        assert_eq!(json_value(Some(Value::Float(f64::NAN))), "\"NaN\"");
+        assert_eq!(json_value(Some(Value::Addr(4))), "\"@4\"");
+        assert_eq!(json_value(None), "null");
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("PRINT(\"a\\b\")"), "\"PRINT(\\\"a\\\\b\\\")\"");
+        assert_eq!(json_string("\u{1}"), "\"\\u0001\"");
+    }
+
+    #[test]
+    fn test_covers() {
+        let tracer = Tracer::new(Vec::new()).range(2..4);
+
+        assert!(!tracer.covers(1));
+        assert!(tracer.covers(2));
+        assert!(!tracer.covers(4));
+    }
+}
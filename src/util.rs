@@ -1,47 +1,29 @@
+use std::fs;
 use std::path::Path;
-use std::fs::File;
-use std::error::Error;
-use std::io::prelude::*;
-use std::io::BufReader;
-use op::OpCode;
 use parser::Parser;
+use program::Program;
 
-/// Given a file name and a Parser struct, open that file,
-/// read it line by line (as strings), and convert each
-/// line into a valid opcode.  Each valid opcode is added
-/// to a vector, which is returned. This vector can then be
-/// read by the vm.
+/// Given a file name, read that file and parse each of its lines
+/// into a valid opcode. The opcodes are returned as a Program,
+/// along with the labels they declare, which can then be run by
+/// the vm.
 ///
 /// This function panics on parse errors, or when the filename
 /// provided cannot be opened.
-pub fn read_file(filename: String, mut parser: Parser) -> Vec<OpCode> {
+pub fn read_file(filename: String) -> Program {
     let path = Path::new(&filename);
     let display = path.display();
 
-    let input_file = match File::open(&path) {
-        Err(error) => panic!("tyr: Failed to open {}: {}", display, Error::description(&error)),
-        Ok(file) => file
+    let source = match fs::read_to_string(path) {
+        Err(error) => panic!("tyr: Failed to open {}: {}", display, error),
+        Ok(source) => source
     };
 
-    let reader = BufReader::new(input_file);
-    let mut instructions: Vec<OpCode> = Vec::new();
-
-    for line in reader.lines() {
-        match line {
-            Err(_) => panic!("tyr: cannot parse line {:?}", line),
-            Ok(result) => {
-                // TODO: Make this a try?
-                let op = match parser.parse_line(&result) {
-                    Ok(op) => op,
-                    Err(error) => panic!("tyr: {:?}", error)
-                };
-
-                instructions.push(op);
-            }
-        }
-    };
-
-    instructions
+    // TODO: Make this a try?
+    match Parser::new().parse(&source) {
+        Ok(program) => program,
+        Err(error) => panic!("tyr: {:?}", error)
+    }
 }
 
 /// Convert an i64 to usize. If the i64 cannot be converted
@@ -0,0 +1,99 @@
+use std::fmt;
+
+/// A single value held on the stack, in memory or on the heap. Each
+/// value carries a tag describing what kind of data it holds, so that
+/// the vm can refuse to perform an operation on the wrong kind of data.
+///
+/// Int: A 64-bit signed integer.
+/// Bool: A boolean, as produced by logical operations on booleans.
+/// Addr: An address into the heap, as produced by ALLOC.
+/// Float: A 64-bit IEEE 754 floating point number.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Bool(bool),
+    Addr(usize),
+    Float(f64)
+}
+
+/// The kind of data held by a Value, without the data itself. This is
+/// used to describe what an operation expected when it is given the
+/// wrong kind of value.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ValueKind {
+    Int,
+    Bool,
+    Addr,
+    Float
+}
+
+impl Value {
+    pub fn kind(&self) -> ValueKind {
+        match *self {
+            Value::Int(_) => ValueKind::Int,
+            Value::Bool(_) => ValueKind::Bool,
+            Value::Addr(_) => ValueKind::Addr,
+            Value::Float(_) => ValueKind::Float
+        }
+    }
+}
+
+impl Default for Value {
+    fn default() -> Value {
+        Value::Int(0)
+    }
+}
+
+/// Floats are always written with a decimal point or an exponent, so
+/// that they can be told apart from integers. NaN is written as `NaN`,
+/// and infinities as `inf` and `-inf`.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Int(val) => write!(f, "{}", val),
+            Value::Bool(val) => write!(f, "{}", val),
+            Value::Addr(addr) => write!(f, "@{}", addr),
+            Value::Float(val) => write!(f, "{:?}", val)
+        }
+    }
+}
+
+impl fmt::Display for ValueKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ValueKind::Int => write!(f, "int"),
+            ValueKind::Bool => write!(f, "bool"),
+            ValueKind::Addr => write!(f, "addr"),
+            ValueKind::Float => write!(f, "float")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind() {
+        assert_eq!(Value::Int(5).kind(), ValueKind::Int);
+        assert_eq!(Value::Bool(true).kind(), ValueKind::Bool);
+        assert_eq!(Value::Addr(3).kind(), ValueKind::Addr);
+        assert_eq!(Value::Float(1.5).kind(), ValueKind::Float);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Value::Int(-5).to_string(), "-5");
+        assert_eq!(Value::Bool(false).to_string(), "false");
+        assert_eq!(Value::Addr(3).to_string(), "@3");
+    }
+
+    #[test]
+    fn test_display_float() {
+        assert_eq!(Value::Float(2.0).to_string(), "2.0");
+        assert_eq!(Value::Float(-0.25).to_string(), "-0.25");
+        assert_eq!(Value::Float(f64::NAN).to_string(), "NaN");
+        assert_eq!(Value::Float(f64::INFINITY).to_string(), "inf");
+        assert_eq!(Value::Float(f64::NEG_INFINITY).to_string(), "-inf");
+    }
+}
@@ -1,41 +1,316 @@
+use config::{OverflowMode, VmConfig};
+use debug::{Breakpoint, Pause, Watchpoint};
+use format::Template;
+use heap::{Heap, HeapError, HeapImage};
+use host::{HostFunction, HostRegistry, HostResult};
+use interrupt::InterruptHandle;
+use io::{Io, StdIo};
 use op::OpCode;
-use sym_tab::SymbolTable;
-use std::process;
+use profile::Profiler;
+use program::Program;
+use snapshot::{self, Reader, SnapshotError, SnapshotResult, Writer};
+use trace::{TraceRecord, Tracer};
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::sync::Arc;
 use util;
+use value::{Value, ValueKind};
 
-/// Maximum size for program stack.
-const STACK_SIZE: usize = 50;
+/// Describes how a call to Vm::run finished when no runtime
+/// error was encountered.
+///
+/// Halted: A HALT instruction was executed.
+/// Finished: The program counter moved past the last instruction.
+/// OutOfFuel: Not enough fuel was left to execute the next instruction.
+///            The run can be continued by adding fuel and calling run again.
+/// Interrupted: The run was stopped through an InterruptHandle. The pc
+///              points at the next instruction to execute, and calling
+///              run again continues from there.
+/// Paused: The run reached a breakpoint or watchpoint. Calling run again
+///         continues from where it paused.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExitState {
+    Halted,
+    Finished,
+    OutOfFuel,
+    Interrupted,
+    Paused(Pause)
+}
+
+/// Describes what happened when a single instruction was stepped
+/// through with Vm::step.
+///
+/// Continue: The instruction was executed, and the program can go on.
+/// Halted: A HALT instruction was executed.
+/// Finished: The program counter is past the last instruction, so
+///           nothing was executed.
+/// OutOfFuel: Not enough fuel was left to execute the instruction.
+/// Interrupted: The vm was interrupted before the instruction.
+/// Paused: The vm paused at a breakpoint before the instruction, or at a
+///         watchpoint written by the instruction.
+/// Error: The instruction failed with a runtime error. The pc still
+///        points at the failing instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StepOutcome {
+    Continue,
+    Halted,
+    Finished,
+    OutOfFuel,
+    Interrupted,
+    Paused(Pause),
+    Error(VmError)
+}
+
+/// The state of the vm at the point a runtime error was raised.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fault {
+    /// Address of the instruction that failed.
+    pub pc: usize,
+    /// Line of the source file holding the instruction that failed.
+    /// The parser produces one instruction per line, so this is always
+    /// one past the pc.
+    pub line: usize,
+    /// The instruction that failed, or None if the pc was past the end
+    /// of the program, such as when a subroutine called by invoke runs
+    /// off the end.
+    pub op: Option<OpCode>,
+    /// Copy of the live stack, from the bottom up to sp.
+    pub stack: Vec<Value>
+}
+
+/// VmErrors are returned from Vm::run when the program being
+/// executed performs an illegal operation. Each variant carries
+/// a Fault describing where the error happened.
+///
+/// StackOverflow: A value was pushed on to a full stack that cannot grow.
+/// StackUnderflow: An operation needed more values than the stack holds.
+/// IllegalAddress: A load or store used an address outside of memory, or
+///                 an offset moved a heap address below zero.
+/// UndefinedLabel: A jump targeted a label missing from the symbol table.
+/// IllegalJump: An indexed jump computed an address outside of the program.
+/// CallStackOverflow: A CALL was made beyond the maximum call depth.
+/// CallStackUnderflow: A RET was executed outside of any subroutine.
+/// Heap: A heap instruction failed, as described by the HeapError.
+/// TypeMismatch: An operation expected one kind of value, but found another.
+/// InvalidConversion: FTOI was given a float with no integer equivalent.
+/// IntegerOverflow: Integer arithmetic overflowed in checked mode.
+/// DivisionByZero: DIV or MOD was given a divisor of zero, and no trap
+///                 handler was installed to catch it.
+/// Io: Reading input or writing output failed.
+/// EndOfInput: READ was executed after all input had been read.
+/// InvalidInput: READ was given a line that is not an integer.
+/// UndefinedFunction: CALLN named a host function that was never registered.
+/// Host: A host function failed, or returned the wrong number of values.
+/// NoReturn: A subroutine called with Vm::invoke halted, finished, ran
+///           out of fuel, was interrupted or paused before returning.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VmError {
+    StackOverflow(Fault),
+    StackUnderflow(Fault),
+    IllegalAddress(i64, Fault),
+    UndefinedLabel(String, Fault),
+    IllegalJump(i64, Fault),
+    CallStackOverflow(usize, Fault),
+    CallStackUnderflow(Fault),
+    Heap(HeapError, Fault),
+    TypeMismatch(ValueKind, Value, Fault),
+    InvalidConversion(f64, Fault),
+    IntegerOverflow(Fault),
+    DivisionByZero(Fault),
+    Io(io::ErrorKind, Fault),
+    EndOfInput(Fault),
+    InvalidInput(String, Fault),
+    UndefinedFunction(String, Fault),
+    Host(String, String, Fault),
+    NoReturn(String, Fault)
+}
+
+impl VmError {
+    /// Returns the vm state captured when this error was raised.
+    pub fn fault(&self) -> &Fault {
+        match *self {
+            VmError::StackOverflow(ref fault) => fault,
+            VmError::StackUnderflow(ref fault) => fault,
+            VmError::IllegalAddress(_, ref fault) => fault,
+            VmError::UndefinedLabel(_, ref fault) => fault,
+            VmError::IllegalJump(_, ref fault) => fault,
+            VmError::CallStackOverflow(_, ref fault) => fault,
+            VmError::CallStackUnderflow(ref fault) => fault,
+            VmError::Heap(_, ref fault) => fault,
+            VmError::TypeMismatch(_, _, ref fault) => fault,
+            VmError::InvalidConversion(_, ref fault) => fault,
+            VmError::IntegerOverflow(ref fault) => fault,
+            VmError::DivisionByZero(ref fault) => fault,
+            VmError::Io(_, ref fault) => fault,
+            VmError::EndOfInput(ref fault) => fault,
+            VmError::InvalidInput(_, ref fault) => fault,
+            VmError::UndefinedFunction(_, ref fault) => fault,
+            VmError::Host(_, _, ref fault) => fault,
+            VmError::NoReturn(_, ref fault) => fault
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fault = self.fault();
+        match fault.op {
+            Some(ref op) => write!(f, "tyr [line {}, pc {}, {:?}]: ", fault.line, fault.pc, op)?,
+            None => write!(f, "tyr [line {}, pc {}, end of program]: ", fault.line, fault.pc)?
+        }
+
+        match *self {
+            VmError::StackOverflow(_) => write!(f, "Stack overflow"),
+            VmError::StackUnderflow(_) => write!(f, "Stack underflow"),
+            VmError::IllegalAddress(addr, _) => write!(f, "Attempted to access illegal address {}", addr),
+            VmError::UndefinedLabel(ref label, _) => write!(f, "Attempted to jump to undefined label {:?}", label),
+            VmError::IllegalJump(addr, _) => write!(f, "Attempted to jump to illegal location {}", addr),
+            VmError::CallStackOverflow(depth, _) => write!(f, "Call stack overflow, maximum depth is {}", depth),
+            VmError::CallStackUnderflow(_) => write!(f, "Attempted to return from outside of a subroutine"),
+            VmError::Heap(ref error, _) => write!(f, "{}", error),
+            VmError::TypeMismatch(expected, found, _) => {
+                write!(f, "Expected a value of type {}, but found {} {}", expected, found.kind(), found)
+            },
+            VmError::InvalidConversion(val, _) => write!(f, "Cannot convert {:?} to an integer", val),
+            VmError::IntegerOverflow(_) => write!(f, "Integer overflow"),
+            VmError::DivisionByZero(_) => write!(f, "Division by zero"),
+            VmError::Io(kind, _) => write!(f, "I/O error: {}", kind),
+            VmError::EndOfInput(_) => write!(f, "Attempted to read past the end of input"),
+            VmError::InvalidInput(ref line, _) => write!(f, "Expected an integer as input, found {:?}", line),
+            VmError::UndefinedFunction(ref name, _) => write!(f, "Attempted to call undefined host function {:?}", name),
+            VmError::Host(ref name, ref message, _) => write!(f, "Host function {:?} failed: {}", name, message),
+            VmError::NoReturn(ref label, _) => write!(f, "Subroutine {:?} stopped without returning", label)
+        }
+    }
+}
+
+pub type VmResult<T> = Result<T, VmError>;
+
+/// Tells the run loop where to continue after an instruction
+/// has been executed.
+enum Flow {
+    Next,
+    Jump(usize),
+    Halt
+}
+
+/// The comparison made by the EQ family of instructions, and by
+/// the matching conditional jumps.
+#[derive(Clone, Copy)]
+enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge
+}
 
 /// Holds relevant state info for the execution of a program.
 ///
 /// The vm is stack based, with all operations taking their
 /// arguments off of the stack and returning results on top
-/// of the stack. The stack supports 64-bit integers, and is given
-/// a maximum size based on the constant STACK_SIZE, named above.
-pub struct Vm<'p> {
-    /// The program to execute, parsed from a file.
-    prog: &'p Vec<OpCode>,
+/// of the stack. Every slot on the stack holds a tagged Value, so that
+/// operations can check the kind of their arguments. Its initial size,
+/// and whether it may grow past that size, are set by the VmConfig
+/// given to Vm::new.
+///
+/// Variables live in a separate memory segment, which is read and
+/// written through the LOAD and STORE family of instructions. This
+/// keeps the stack free for temporary values. Memory that a program
+/// allocates at run time is held in the heap.
+pub struct Vm<I: Io = StdIo> {
+    /// The program to execute, parsed from a file. It holds the
+    /// addresses of the labels in the program, which are retrieved
+    /// in order to execute jmp instructions.
+    program: Arc<Program>,
     /// Program Counter. Points to the current instruction
     /// in the program (ie. the instruction being executed).
     pc: usize,
-    /// The stack itself, where operations are executed.
-    stack: [i64; STACK_SIZE],
-    /// Stack Pointer. Points to the top of the stack.
-    sp: usize,
-    /// The Symbol Table contains adresses of labels contained
-    /// in the program. These are retrieved in order to execute
-    /// jmp instructions.
-    sym_tab: &'p SymbolTable
+    /// The stack itself, where operations are executed. The top of
+    /// the stack (sp) is the last element of the vector.
+    stack: Vec<Value>,
+    /// Addressable memory holding the program's global variables.
+    memory: Vec<Value>,
+    /// Blocks allocated by the program with ALLOC.
+    heap: Heap,
+    /// Return addresses of the subroutines currently being executed.
+    /// This is kept apart from the stack, so that a subroutine cannot
+    /// clobber the address it returns to.
+    call_stack: Vec<usize>,
+    /// Settings for the stack, memory and call stack limits.
+    config: VmConfig,
+    /// Address of the trap handler installed with TRAP, if any.
+    trap: Option<usize>,
+    /// Behaviour of integer arithmetic that overflows. This starts out
+    /// as the mode in the config, and can be changed by the program.
+    overflow: OverflowMode,
+    /// Streams used by PRINT, EPRINT and READ.
+    io: I,
+    /// Native functions that the program can call with CALLN.
+    host: HostRegistry,
+    /// Fuel left for executing instructions, or None if the run is
+    /// not limited.
+    fuel: Option<u64>,
+    /// Set from outside of the vm to stop the run.
+    interrupt: InterruptHandle,
+    /// Addresses of instructions the run pauses before.
+    breakpoints: BTreeSet<usize>,
+    /// Locations the run pauses after writing to.
+    watchpoints: BTreeSet<Watchpoint>,
+    /// The first watchpoint written by the instruction being executed.
+    watch_hit: Option<Watchpoint>,
+    /// Number of values at the bottom of the stack that the running code
+    /// may not read or pop. This is zero, except during invoke, where it
+    /// protects the values that were on the stack before the call.
+    floor: usize,
+    /// Set when the run paused at a breakpoint, so that resuming executes
+    /// the instruction there instead of pausing again.
+    resume: bool,
+    /// Records each executed instruction, when tracing is enabled.
+    tracer: Option<Tracer>,
+    /// Counts executed instructions, when profiling is enabled.
+    profiler: Option<Profiler>
+}
+
+impl Vm<StdIo> {
+    /// Creates a vm connected to the standard streams of the process.
+    /// The program can be passed in directly, or as an Arc so that
+    /// several vms can share it.
+    pub fn new<P: Into<Arc<Program>>>(program: P, config: VmConfig) -> Vm<StdIo> {
+        Vm::with_io(program, config, StdIo::new())
+    }
 }
 
-impl<'p> Vm<'p> {
-    pub fn new(program: &'p Vec<OpCode>, table: &'p SymbolTable) -> Vm<'p> {
+impl<I: Io> Vm<I> {
+    /// Creates a vm that performs its I/O through the given streams.
+    pub fn with_io<P: Into<Arc<Program>>>(program: P, config: VmConfig, io: I) -> Vm<I> {
+        let program = program.into();
+
         Vm {
-            prog: program,
-            pc: 0,
-            stack: [0; STACK_SIZE],
-            sp: 0,
-            sym_tab: table
+            pc: program.get_entry(),
+            program,
+            stack: Vec::with_capacity(config.get_stack_size()),
+            memory: vec![Value::default(); config.get_memory_size()],
+            heap: Heap::new(config.get_heap_size()),
+            trap: None,
+            overflow: config.get_overflow(),
+            call_stack: Vec::new(),
+            fuel: config.get_fuel(),
+            config,
+            io,
+            host: HostRegistry::new(),
+            interrupt: InterruptHandle::new(),
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            watch_hit: None,
+            floor: 0,
+            resume: false,
+            tracer: None,
+            profiler: None
         }
     }
 
@@ -49,584 +324,3808 @@ impl<'p> Vm<'p> {
     /// This logic is similar to the standard vm operation of fetch, decode,
     /// execute, except the operations are decoded in the parsing phase.
     ///
-    /// This function will terminate on any errors encountered during the
-    /// execute phase, with a panic. This is sort of like a run time error in
-    /// a regular program.
+    /// Any error encountered during the execute phase stops the program
+    /// and is returned as a VmError, sort of like a run time error in
+    /// a regular program. The vm never panics or exits the process.
+    /// A trace that cannot be flushed once the run stops is reported as
+    /// a VmError::Io, whose fault holds no instruction if the program
+    /// ran to its end.
     ///
     /// ## Example
     ///
     /// ```
-    /// use tyr::vm::Vm;
+    /// use tyr::vm::{Vm, ExitState};
+    /// use tyr::config::VmConfig;
     /// use tyr::op::OpCode;
+    /// use tyr::program::Program;
     /// use tyr::sym_tab::SymbolTable;
     ///
-    /// let prog = vec![OpCode::PRINT("Hello World".to_string())];
+    /// let prog = vec![OpCode::PRINT("Hello World".to_string()), OpCode::HALT];
     /// let sym_tab = SymbolTable::new();
-    /// let mut vm = Vm::new(&prog, &sym_tab);
+    /// let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
     ///
-    /// vm.run()
+    /// assert_eq!(vm.run(), Ok(ExitState::Halted));
     /// ```
-    pub fn run(&mut self) {
-        loop {
-            if self.pc >= self.prog.len() {
-                break;
+    pub fn run(&mut self) -> VmResult<ExitState> {
+        let result = self.run_cycles();
+        if let Some(ref mut tracer) = self.tracer {
+            if let Err(error) = tracer.flush() {
+                return result.and(Err(VmError::Io(error.kind(), self.fault())));
             }
+        }
 
-            let ref curr_instr = self.prog[self.pc];
-            self.execute(curr_instr);
+        result
+    }
 
-            self.pc = self.pc + 1;
+    /// Executes instructions until the program stops.
+    fn run_cycles(&mut self) -> VmResult<ExitState> {
+        let program = Arc::clone(&self.program);
+        loop {
+            if let Some(state) = self.cycle(&program)? {
+                return Ok(state);
+            }
         }
     }
 
-    /// Given an instruction opcode, execute it by calling the corresponding
-    /// function implemented below. This function should only be called
-    /// by the run() function above.
+    /// Executes exactly one instruction, the one at the pc, and moves
+    /// the pc on to the next instruction. Fuel and interrupts are handled
+    /// the same way as in run, so a program can be driven one step at a
+    /// time by a debugger or visualizer.
+    ///
+    /// ## Example
     ///
-    /// This function can panic for several reasons:
+    /// ```
+    /// use tyr::config::VmConfig;
+    /// use tyr::op::OpCode;
+    /// use tyr::program::Program;
+    /// use tyr::sym_tab::SymbolTable;
+    /// use tyr::value::Value;
+    /// use tyr::vm::{StepOutcome, Vm};
     ///
-    /// 1. A jump is encountered to a label that doesn't exist.
-    /// 2. A label has been defined more than once in a program.
-    /// 3. The stack overflows/underflows.
-    /// 4. An illegal value is placed on the stack, and an operation fails
-    ///    because of that value.
-    fn execute(&mut self, instr: &OpCode) {
-        match instr.clone() {
-            OpCode::LOADC(val) => self.loadc(val),
-            OpCode::ADD => self.add(),
-            OpCode::SUB => self.sub(),
-            OpCode::MUL => self.mul(),
-            OpCode::DIV => self.div(),
-            OpCode::MOD => self.modq(),
-            OpCode::AND => self.and(),
-            OpCode::OR => self.or(),
-            OpCode::NEG => self.neg(),
-            OpCode::HALT => process::exit(0),
-            OpCode::LOAD => self.load(),
-            OpCode::STORE => self.store(),
-            OpCode::JMP(label) => self.jmp(label),
-            OpCode::JMPZ(label) => self.jmpz(label),
-            OpCode::JMPI(offset) => self.jmpi(offset),
-            OpCode::PRINT(message) => println!("{}", message),
-            OpCode::LOADV(val) => self.loadv(val),
-            OpCode::STOREV(val) => self.storev(val),
-            OpCode::DUP => self.dup(),
-            OpCode::LABEL(_, _) => {},
-            OpCode::NOP => {}
+    /// let prog = vec![OpCode::LOADC(1), OpCode::HALT];
+    /// let sym_tab = SymbolTable::new();
+    /// let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+    ///
+    /// assert_eq!(vm.current_instruction(), Some(&OpCode::LOADC(1)));
+    /// assert_eq!(vm.step(), StepOutcome::Continue);
+    /// assert_eq!(vm.stack(), &[Value::Int(1)]);
+    /// assert_eq!(vm.step(), StepOutcome::Halted);
+    /// ```
+    pub fn step(&mut self) -> StepOutcome {
+        let program = Arc::clone(&self.program);
+        match self.cycle(&program) {
+            Ok(None) => StepOutcome::Continue,
+            Ok(Some(ExitState::Halted)) => StepOutcome::Halted,
+            Ok(Some(ExitState::Finished)) => StepOutcome::Finished,
+            Ok(Some(ExitState::OutOfFuel)) => StepOutcome::OutOfFuel,
+            Ok(Some(ExitState::Interrupted)) => StepOutcome::Interrupted,
+            Ok(Some(ExitState::Paused(pause))) => StepOutcome::Paused(pause),
+            Err(error) => StepOutcome::Error(error)
         }
     }
 
-    /// Increase the stack pointer by one. Panics if the stack pointer
-    /// goes above the maximum stack size.
-    fn push(&mut self) {
-        if self.sp == STACK_SIZE {
-            panic!("tyr: Stack overflow");
+    /// Calls the subroutine at the given label from Rust. The arguments
+    /// are pushed in order, then the vm runs from the label until the
+    /// subroutine returns with RET. Whatever the subroutine leaves on
+    /// the stack above its arguments' starting point is taken off and
+    /// returned, so the stack is left as it was before the call. The
+    /// subroutine can only see its own arguments: popping past them is
+    /// a stack underflow, and leaves the values below them untouched.
+    ///
+    /// The pc is restored once the call is done, whether it succeeded or
+    /// not, and memory and the heap keep any changes made by the call.
+    /// This means invoke can be called any number of times, before or
+    /// after run. If the subroutine executes HALT or runs past the end of
+    /// the program instead of returning, a VmError::NoReturn is returned.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use tyr::config::VmConfig;
+    /// use tyr::op::OpCode;
+    /// use tyr::program::Program;
+    /// use tyr::sym_tab::SymbolTable;
+    /// use tyr::value::Value;
+    /// use tyr::vm::Vm;
+    ///
+    /// let prog = vec![
+    ///     OpCode::HALT,
+    ///     OpCode::LABEL("double".to_string(), 1),
+    ///     OpCode::DUP,
+    ///     OpCode::ADD,
+    ///     OpCode::RET
+    /// ];
+    /// let mut sym_tab = SymbolTable::new();
+    /// sym_tab.insert("double".to_string(), 1);
+    /// let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+    ///
+    /// assert_eq!(vm.invoke("double", &[Value::Int(21)]), Ok(vec![Value::Int(42)]));
+    /// ```
+    pub fn invoke(&mut self, label: &str, args: &[Value]) -> VmResult<Vec<Value>> {
+        let pc = self.pc;
+        let base = self.stack.len();
+        let depth = self.call_stack.len();
+        let floor = mem::replace(&mut self.floor, base);
+        let resume = mem::replace(&mut self.resume, false);
+
+        let result = self.invoke_at(label, args, depth);
+        let values = self.stack.split_off(base);
+        self.call_stack.truncate(depth);
+        self.floor = floor;
+        self.resume = resume;
+        self.pc = pc;
+
+        result.map(|_| values)
+    }
+
+    /// Runs the subroutine for invoke, until the call stack drops back
+    /// down to the given depth.
+    fn invoke_at(&mut self, label: &str, args: &[Value], depth: usize) -> VmResult<()> {
+        let addr = self.jmp(label)?;
+        for arg in args {
+            self.push(*arg)?;
+        }
+
+        self.pc = self.call_addr(addr)?;
+        let program = Arc::clone(&self.program);
+        while self.call_stack.len() > depth {
+            if self.cycle(&program)?.is_some() {
+                return Err(VmError::NoReturn(label.to_string(), self.fault()));
+            }
         }
-        self.sp = self.sp + 1;
+
+        Ok(())
     }
 
-    /// Decrease the stack pointer by one. Panics if the stack pointer goes
-    /// below zero.
-    fn pop(&mut self) {
-        if self.sp == 0 {
-            panic!("tyr: Stack underflow");
+    /// Executes the instruction at the pc, then moves the pc on to the
+    /// next instruction to execute. Returns the exit state once the
+    /// program has stopped.
+    ///
+    /// The caller clones the vm's Arc of the program once for the whole
+    /// run and passes the program in, so that executing an instruction
+    /// never touches the reference count.
+    ///
+    /// A waiting interrupt is consumed before checking whether the program
+    /// has finished. An interrupt that arrives while the last instruction
+    /// runs therefore stops the current run, instead of staying set and
+    /// aborting the next call to run, step or invoke.
+    fn cycle(&mut self, program: &Program) -> VmResult<Option<ExitState>> {
+        if self.interrupt.take() {
+            return Ok(Some(ExitState::Interrupted));
+        }
+
+        if self.pc >= program.len() {
+            return Ok(Some(ExitState::Finished));
+        }
+
+        if !self.resume && self.breakpoints.contains(&self.pc) {
+            self.resume = true;
+            return Ok(Some(ExitState::Paused(Pause::Breakpoint(self.pc))));
+        }
+
+        if let Some(fuel) = self.fuel {
+            let cost = self.config.get_fuel_cost();
+            if fuel < cost {
+                return Ok(Some(ExitState::OutOfFuel));
+            }
+            self.fuel = Some(fuel - cost);
+        }
+
+        self.watch_hit = None;
+        let prog = program.code();
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.record(self.pc, &prog[self.pc], &self.call_stack);
+        }
+
+        let before = self.stack.last().cloned();
+        let result = match self.execute(&prog[self.pc]) {
+            Err(VmError::DivisionByZero(_)) if self.trap.is_some() => self.enter_trap(),
+            result => result
+        };
+        self.resume = false;
+
+        // The instruction is traced even when it failed, before its error
+        // is returned, so a trace always ends with the faulting instruction.
+        let traced = match self.tracer {
+            Some(_) => self.trace(&prog[self.pc], before, &result),
+            None => Ok(())
+        };
+        let flow = result?;
+        traced?;
+
+        match flow {
+            Flow::Next => self.pc += 1,
+            Flow::Jump(addr) => self.pc = addr,
+            Flow::Halt => return Ok(Some(ExitState::Halted))
+        }
+
+        match self.watch_hit.take() {
+            Some(watch) => Ok(Some(ExitState::Paused(Pause::Watchpoint(watch)))),
+            None => Ok(None)
         }
-        self.sp = self.sp - 1;
     }
 
-    /// Loads a constant on to the stack.
+    /// Returns the program counter, the address of the next instruction
+    /// to execute.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Returns the stack pointer, the number of values on the stack.
+    /// The top of the stack is at sp - 1.
+    pub fn sp(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Returns the live values on the stack, from the bottom up.
+    pub fn stack(&self) -> &[Value] {
+        &self.stack
+    }
+
+    /// Returns the instruction at the pc, or None once the pc has
+    /// moved past the end of the program.
+    pub fn current_instruction(&self) -> Option<&OpCode> {
+        self.program.get(self.pc)
+    }
+
+    /// Returns the program being executed.
+    pub fn program(&self) -> &Arc<Program> {
+        &self.program
+    }
+
+    /// Returns the value on top of the stack, or None if the
+    /// stack is empty.
+    pub fn peek(&self) -> Option<Value> {
+        self.stack.last().cloned()
+    }
+
+    /// Returns the heap, so that blocks left allocated once the
+    /// program has finished can be reported.
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    /// Registers a native function that the program can call with CALLN.
+    /// The function is given `arity` values popped from the stack, in the
+    /// order they were pushed, and must return exactly `returns` values,
+    /// which are pushed in order. An error returned by the function stops
+    /// the program with a VmError::Host.
     ///
-    /// By calling:
+    /// ## Example
     ///
-    /// LOADC 5
+    /// ```
+    /// use tyr::config::VmConfig;
+    /// use tyr::op::OpCode;
+    /// use tyr::program::Program;
+    /// use tyr::sym_tab::SymbolTable;
+    /// use tyr::value::Value;
+    /// use tyr::vm::Vm;
     ///
-    /// The stack will look like:
+    /// let prog = vec![OpCode::LOADC(4), OpCode::CALLN("square".to_string())];
+    /// let sym_tab = SymbolTable::new();
+    /// let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+    /// vm.register("square", 1, 1, |args| match args[0] {
+    ///     Value::Int(val) => Ok(vec![Value::Int(val * val)]),
+    ///     _ => Err("expected an integer".to_string())
+    /// });
+    /// vm.run().unwrap();
     ///
-    /// | 5 | <-- sp
-    /// | 0 | <-- bottom of stack
-    /// +---+
-    fn loadc(&mut self, value: i64) {
-        self.push();
-        self.stack[self.sp] = value;
+    /// assert_eq!(vm.peek(), Some(Value::Int(16)));
+    /// ```
+    pub fn register<F>(&mut self, name: &str, arity: usize, returns: usize, func: F)
+        where F: FnMut(&[Value]) -> HostResult + 'static
+    {
+        self.host.insert(name.to_string(), HostFunction {
+            arity,
+            returns,
+            func: Box::new(func)
+        });
     }
 
-    /// Adds the top two numbers on the stack, and returns the
-    /// result on the top of the stack.
+    /// Returns the fuel left, or None if the run is not limited.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Adds fuel, so that a run stopped with ExitState::OutOfFuel can
+    /// be continued by calling run again. This also starts limiting a
+    /// vm that was created without any fuel limit.
     ///
-    /// Consider the following sequence of operations:
+    /// ## Example
     ///
-    /// LOADC 5
-    /// LOADC 5
-    /// ADD
+    /// ```
+    /// use tyr::config::VmConfig;
+    /// use tyr::op::OpCode;
+    /// use tyr::program::Program;
+    /// use tyr::sym_tab::SymbolTable;
+    /// use tyr::vm::{ExitState, Vm};
     ///
-    /// After execution, the stack will look like the following:
+    /// let prog = vec![OpCode::LOADC(1), OpCode::LOADC(2), OpCode::ADD];
+    /// let sym_tab = SymbolTable::new();
+    /// let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new().fuel(2));
     ///
-    /// | 10 | <-- sp
-    /// |  5 | <-- the first argument is still present at sp-1
-    /// |  0 | <-- bottom of stack
-    /// +----+
-    fn add(&mut self) {
-        self.stack[self.sp-1] = self.stack[self.sp] + self.stack[self.sp-1];
-        self.pop();
+    /// assert_eq!(vm.run(), Ok(ExitState::OutOfFuel));
+    /// vm.add_fuel(1);
+    /// assert_eq!(vm.run(), Ok(ExitState::Finished));
+    /// ```
+    pub fn add_fuel(&mut self, amount: u64) {
+        self.fuel = Some(self.fuel.unwrap_or(0).saturating_add(amount));
     }
 
-    /// Multiplies the top two numbers on the stack, and returns the
-    /// result on the top of the stack.
-    fn mul(&mut self) {
-        self.stack[self.sp-1] = self.stack[self.sp] * self.stack[self.sp-1];
-        self.pop();
+    /// Sets a breakpoint, so that run and step pause before executing the
+    /// instruction it points at. Resuming after the pause executes that
+    /// instruction. Returns the address of the instruction, or None if
+    /// the breakpoint names a label that does not exist, or a line
+    /// outside of the program.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use tyr::config::VmConfig;
+    /// use tyr::debug::{Breakpoint, Pause};
+    /// use tyr::op::OpCode;
+    /// use tyr::program::Program;
+    /// use tyr::sym_tab::SymbolTable;
+    /// use tyr::vm::{ExitState, Vm};
+    ///
+    /// let prog = vec![OpCode::LOADC(1), OpCode::LOADC(2), OpCode::ADD];
+    /// let sym_tab = SymbolTable::new();
+    /// let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+    /// vm.add_breakpoint(Breakpoint::Line(3));
+    ///
+    /// assert_eq!(vm.run(), Ok(ExitState::Paused(Pause::Breakpoint(2))));
+    /// assert_eq!(vm.run(), Ok(ExitState::Finished));
+    /// ```
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> Option<usize> {
+        let addr = self.breakpoint_addr(&breakpoint)?;
+        self.breakpoints.insert(addr);
+
+        Some(addr)
     }
 
-    /// Subtracts the top two numbers on the stack, and returns the
-    /// result on the top of the stack.
-    fn sub(&mut self) {
-        self.stack[self.sp-1] = self.stack[self.sp] - self.stack[self.sp-1];
-        self.pop();
+    /// Removes a breakpoint, returning whether one was set.
+    pub fn remove_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+        match self.breakpoint_addr(&breakpoint) {
+            Some(addr) => self.breakpoints.remove(&addr),
+            None => false
+        }
     }
 
-    /// Divides the top two numbers on the stack, and returns the
-    /// result on the top of the stack.
-    fn div(&mut self) {
-        self.stack[self.sp-1] = self.stack[self.sp] / self.stack[self.sp-1];
-        self.pop();
+    /// Finds the address of the instruction a breakpoint points at, using
+    /// the source lines recorded in the program for line breakpoints.
+    fn breakpoint_addr(&self, breakpoint: &Breakpoint) -> Option<usize> {
+        let addr = match *breakpoint {
+            Breakpoint::Pc(addr) => addr,
+            Breakpoint::Label(ref label) => self.program.label(label)?,
+            Breakpoint::Line(line) => self.program.addr_of_line(line)?
+        };
+
+        if addr < self.program.len() {
+            Some(addr)
+        } else {
+            None
+        }
     }
 
-    /// Mods the top two numbers on the stack, and returns the
-    /// result on the top of the stack.
-    fn modq(&mut self) {
-        self.stack[self.sp-1] = self.stack[self.sp] % self.stack[self.sp-1];
-        self.pop();
+    /// Sets a watchpoint, so that run and step pause after any instruction
+    /// that writes to the watched stack slot or memory cell. Writing the
+    /// value already held there still counts as a write.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.insert(watchpoint);
     }
 
-    /// Performs a bitwise AND on the top two numbers on the stack,
-    /// and returns the result on the top of the stack.
-    fn and(&mut self) {
-        self.stack[self.sp-1] = self.stack[self.sp] & self.stack[self.sp-1];
-        self.pop();
+    /// Removes a watchpoint, returning whether one was set.
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        self.watchpoints.remove(&watchpoint)
     }
 
-    /// Performs a bitwise OR on the top two numbers on the stack,
-    /// and returns the result on the top of the stack.
-    fn or(&mut self) {
-        self.stack[self.sp-1] = self.stack[self.sp] | self.stack[self.sp-1];
-        self.pop();
+    /// Enables tracing, so that a record of each instruction executed from
+    /// now on is written to the tracer. Any tracer already set is replaced.
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
     }
 
-    /// Negates the top value on the stack, while keeping sp the same.
+    /// Enables profiling, so that every instruction executed from now on
+    /// is counted by the profiler. Any profiler already set is replaced.
     ///
-    /// Consider the following sequense of operations:
+    /// ## Example
     ///
-    /// LOADC 5
-    /// NEG
+    /// ```
+    /// use tyr::config::VmConfig;
+    /// use tyr::op::OpCode;
+    /// use tyr::profile::Profiler;
+    /// use tyr::program::Program;
+    /// use tyr::sym_tab::SymbolTable;
+    /// use tyr::vm::Vm;
     ///
-    /// After exection, the stack will look like the following:
+    /// let prog = vec![OpCode::LOADC(1), OpCode::LOADC(2), OpCode::ADD];
+    /// let sym_tab = SymbolTable::new();
+    /// let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+    /// let profiler = Profiler::new(vm.program().sym_tab());
+    /// vm.set_profiler(profiler);
+    /// vm.run().unwrap();
     ///
-    /// | -5 | <-- sp
-    /// |  0 | <-- bottom of stack
-    /// +----+
-    fn neg(&mut self) {
-        self.stack[self.sp] = -self.stack[self.sp];
+    /// let profiler = vm.profiler().unwrap();
+    /// assert_eq!(profiler.op_counts().get("LOADC"), Some(&2));
+    /// ```
+    pub fn set_profiler(&mut self, profiler: Profiler) {
+        self.profiler = Some(profiler);
+    }
+
+    /// Returns the profiler, if profiling is enabled.
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Writes a trace record for the instruction just executed at the pc.
+    fn trace(&mut self, op: &OpCode, before: Option<Value>, result: &VmResult<Flow>) -> VmResult<()> {
+        let record = match *result {
+            Ok(ref flow) => TraceRecord {
+                pc: self.pc,
+                op,
+                before,
+                after: self.stack.last().cloned(),
+                jump: match *flow {
+                    Flow::Jump(addr) => Some(addr),
+                    Flow::Next | Flow::Halt => None
+                },
+                error: None
+            },
+            Err(ref error) => TraceRecord {
+                pc: self.pc,
+                op,
+                before,
+                after: None,
+                jump: None,
+                error: Some(error.to_string())
+            }
+        };
+
+        let result = match self.tracer {
+            Some(ref mut tracer) => tracer.record(&record),
+            None => Ok(())
+        };
+        result.map_err(|e| self.io_fault(e))
+    }
+
+    /// Captures the runtime state of the vm in a versioned binary format:
+    /// the pc, the stack, memory, the heap, the call stack, the installed
+    /// trap handler, the overflow mode and the fuel left. Breakpoints,
+    /// watchpoints, host functions, I/O, tracing and profiling belong to
+    /// the embedder and are not included.
+    ///
+    /// The snapshot holds a fingerprint of the program, so it can only be
+    /// restored into a vm running the same program.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use tyr::config::VmConfig;
+    /// use tyr::op::OpCode;
+    /// use tyr::program::Program;
+    /// use tyr::sym_tab::SymbolTable;
+    /// use tyr::value::Value;
+    /// use tyr::vm::{ExitState, Vm};
+    /// use std::sync::Arc;
+    ///
+    /// let prog = vec![OpCode::LOADC(1), OpCode::LOADC(2), OpCode::ADD];
+    /// let program = Arc::new(Program::new(prog, SymbolTable::new()));
+    /// let mut vm = Vm::new(Arc::clone(&program), VmConfig::new().fuel(2));
+    /// vm.run().unwrap();
+    /// let snapshot = vm.snapshot();
+    ///
+    /// let mut other = Vm::new(program, VmConfig::new());
+    /// other.restore(&snapshot).unwrap();
+    /// other.add_fuel(1);
+    ///
+    /// assert_eq!(other.run(), Ok(ExitState::Finished));
+    /// assert_eq!(other.peek(), Some(Value::Int(3)));
+    /// ```
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut out = Writer::new();
+        for byte in snapshot::MAGIC {
+            out.u8(*byte);
+        }
+        out.u16(snapshot::VERSION);
+        out.u64(snapshot::fingerprint(self.program.code()));
+
+        out.usize(self.pc);
+        out.u8(match self.overflow {
+            OverflowMode::Wrapping => 0,
+            OverflowMode::Checked => 1,
+            OverflowMode::Saturating => 2
+        });
+        out.option(self.trap.map(|addr| addr as u64));
+        out.option(self.fuel);
+        out.values(&self.stack);
+        out.values(&self.memory);
+
+        out.usize(self.call_stack.len());
+        for addr in &self.call_stack {
+            out.usize(*addr);
+        }
+
+        let heap = self.heap.image();
+        out.usize(heap.blocks.len());
+        for &(addr, ref block) in &heap.blocks {
+            out.usize(addr);
+            out.values(block);
+        }
+        out.usize(heap.freed.len());
+        for &(addr, size) in &heap.freed {
+            out.usize(addr);
+            out.usize(size);
+        }
+        out.usize(heap.next_addr);
+
+        out.into_bytes()
+    }
+
+    /// Replaces the runtime state of the vm with one captured by snapshot.
+    /// Nothing is changed if the snapshot cannot be read, was written in
+    /// another format version, or was taken from a different program.
+    pub fn restore(&mut self, bytes: &[u8]) -> SnapshotResult<()> {
+        let mut input = Reader::new(bytes);
+        for byte in snapshot::MAGIC {
+            if input.u8()? != *byte {
+                return Err(SnapshotError::InvalidFormat("Missing snapshot header".to_string()));
+            }
+        }
+
+        let version = input.u16()?;
+        if version != snapshot::VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        if input.u64()? != snapshot::fingerprint(self.program.code()) {
+            return Err(SnapshotError::ProgramMismatch);
+        }
+
+        let pc = input.usize()?;
+        let overflow = match input.u8()? {
+            0 => OverflowMode::Wrapping,
+            1 => OverflowMode::Checked,
+            2 => OverflowMode::Saturating,
+            mode => return Err(SnapshotError::InvalidFormat(format!("Unknown overflow mode {}", mode)))
+        };
+        let trap = input.option()?.map(|addr| addr as usize);
+        let fuel = input.option()?;
+        let stack = input.values()?;
+        let memory = input.values()?;
+
+        let count = input.count(8)?;
+        let mut call_stack = Vec::with_capacity(count);
+        for _ in 0..count {
+            call_stack.push(input.usize()?);
+        }
+
+        let count = input.count(16)?;
+        let mut blocks = Vec::with_capacity(count);
+        for _ in 0..count {
+            let addr = input.usize()?;
+            blocks.push((addr, input.values()?));
+        }
+        let count = input.count(16)?;
+        let mut freed = Vec::with_capacity(count);
+        for _ in 0..count {
+            let addr = input.usize()?;
+            freed.push((addr, input.usize()?));
+        }
+        let image = HeapImage { blocks, freed, next_addr: input.usize()? };
+        input.finish()?;
+        image.validate().map_err(SnapshotError::InvalidFormat)?;
+
+        let len = self.program.len();
+        if pc > len || trap.is_some_and(|addr| addr > len) || call_stack.iter().any(|addr| *addr > len) {
+            return Err(SnapshotError::InvalidFormat("Address outside of the program".to_string()));
+        }
+
+        self.pc = pc;
+        self.overflow = overflow;
+        self.trap = trap;
+        self.fuel = fuel;
+        self.stack = stack;
+        self.memory = memory;
+        self.call_stack = call_stack;
+        self.heap = Heap::from_image(image, self.config.get_heap_size());
+        self.watch_hit = None;
+        self.resume = false;
+
+        Ok(())
+    }
+
+    /// Returns a handle that can stop this vm while it is running. The
+    /// handle can be sent to another thread.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// use std::thread;
+    /// use tyr::config::VmConfig;
+    /// use tyr::op::OpCode;
+    /// use tyr::program::Program;
+    /// use tyr::sym_tab::SymbolTable;
+    /// use tyr::vm::{ExitState, Vm};
+    ///
+    /// let prog = vec![OpCode::LABEL("loop".to_string(), 0), OpCode::JMP("loop".to_string())];
+    /// let mut sym_tab = SymbolTable::new();
+    /// sym_tab.insert("loop".to_string(), 0);
+    /// let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+    ///
+    /// let handle = vm.interrupt_handle();
+    /// thread::spawn(move || handle.interrupt());
+    ///
+    /// assert_eq!(vm.run(), Ok(ExitState::Interrupted));
+    /// ```
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// Returns the streams used for I/O, so that output collected
+    /// in memory can be inspected.
+    pub fn io(&self) -> &I {
+        &self.io
+    }
+
+    /// Given an instruction opcode, execute it by calling the corresponding
+    /// function implemented below. This function should only be called
+    /// by the run() function above.
+    ///
+    /// This function returns an error for several reasons:
+    ///
+    /// 1. A jump is encountered to a label that doesn't exist.
+    /// 2. The stack overflows/underflows.
+    /// 3. An illegal value is placed on the stack, and an operation fails
+    ///    because of that value.
+    fn execute(&mut self, instr: &OpCode) -> VmResult<Flow> {
+        match *instr {
+            OpCode::LOADC(val) => self.loadc(val)?,
+            OpCode::ADD => self.add()?,
+            OpCode::SUB => self.sub()?,
+            OpCode::MUL => self.mul()?,
+            OpCode::DIV => self.div()?,
+            OpCode::MOD => self.modq()?,
+            OpCode::AND => self.and()?,
+            OpCode::OR => self.or()?,
+            OpCode::NEG => self.neg()?,
+            OpCode::EQ => self.cmp(Cmp::Eq)?,
+            OpCode::NE => self.cmp(Cmp::Ne)?,
+            OpCode::LT => self.cmp(Cmp::Lt)?,
+            OpCode::LE => self.cmp(Cmp::Le)?,
+            OpCode::GT => self.cmp(Cmp::Gt)?,
+            OpCode::GE => self.cmp(Cmp::Ge)?,
+            OpCode::LOADF(val) => self.loadf(val)?,
+            OpCode::FADD => self.fadd()?,
+            OpCode::FSUB => self.fsub()?,
+            OpCode::FMUL => self.fmul()?,
+            OpCode::FDIV => self.fdiv()?,
+            OpCode::ITOF => self.itof()?,
+            OpCode::FTOI => self.ftoi()?,
+            OpCode::HALT => return Ok(Flow::Halt),
+            OpCode::LOAD => self.load()?,
+            OpCode::STORE => self.store()?,
+            OpCode::JMP(ref label) => return self.jmp(label).map(Flow::Jump),
+            OpCode::JMPZ(ref label) => return self.jmpz(label),
+            OpCode::JMPNZ(ref label) => return self.jmpnz(label),
+            OpCode::JEQ(ref label) => return self.jmp_cmp(Cmp::Eq, label),
+            OpCode::JNE(ref label) => return self.jmp_cmp(Cmp::Ne, label),
+            OpCode::JLT(ref label) => return self.jmp_cmp(Cmp::Lt, label),
+            OpCode::JLE(ref label) => return self.jmp_cmp(Cmp::Le, label),
+            OpCode::JGT(ref label) => return self.jmp_cmp(Cmp::Gt, label),
+            OpCode::JGE(ref label) => return self.jmp_cmp(Cmp::Ge, label),
+            OpCode::JMPI(offset) => return self.jmpi(offset).map(Flow::Jump),
+            OpCode::CALL(ref label) => return self.call(label).map(Flow::Jump),
+            OpCode::RET => return self.ret().map(Flow::Jump),
+            OpCode::CALLN(ref name) => self.calln(name)?,
+            OpCode::TRAP(ref label) => self.trap = Some(self.jmp(label)?),
+            OpCode::ALLOC(size) => self.alloc(size)?,
+            OpCode::FREE => self.free()?,
+            OpCode::LOADH => self.loadh()?,
+            OpCode::STOREH => self.storeh()?,
+            OpCode::PRINT(ref message) => self.print(message)?,
+            OpCode::PRINTV => self.printv()?,
+            OpCode::PRINTF(ref template) => self.printf(template)?,
+            OpCode::EPRINT(ref message) => self.eprint(message)?,
+            OpCode::READ => self.read()?,
+            OpCode::LOADV(val) => self.loadv(val)?,
+            OpCode::STOREV(val) => self.storev(val)?,
+            OpCode::DUP => self.dup()?,
+            OpCode::SWAP => self.swap()?,
+            OpCode::DROP => { self.pop()?; },
+            OpCode::OVER => self.over()?,
+            OpCode::ROT => self.rot()?,
+            OpCode::PICK(n) => self.pick(n)?,
+            OpCode::DEPTH => self.depth()?,
+            OpCode::OVERFLOW(mode) => self.overflow = mode,
+            OpCode::LABEL(_, _) => {},
+            OpCode::NOP => {}
+        }
+
+        Ok(Flow::Next)
+    }
+
+    /// Captures the current state of the vm, so that it can be
+    /// attached to a VmError.
+    fn fault(&self) -> Fault {
+        Fault {
+            pc: self.pc,
+            line: self.program.line(self.pc).unwrap_or(self.pc + 1),
+            op: self.program.get(self.pc).cloned(),
+            stack: self.stack.clone()
+        }
+    }
+
+    /// Place a value on top of the stack. The stack grows past its
+    /// initial size when the config allows it. Returns an error if
+    /// the stack is already at its limit.
+    fn push(&mut self, value: Value) -> VmResult<()> {
+        if self.stack.len() >= self.config.stack_limit() {
+            return Err(VmError::StackOverflow(self.fault()));
+        }
+        let slot = self.stack.len();
+        self.stack.push(value);
+        self.written(Watchpoint::Stack(slot));
+
+        Ok(())
+    }
+
+    /// Remove and return the value on top of the stack. Returns an
+    /// error if the stack is empty.
+    fn pop(&mut self) -> VmResult<Value> {
+        let value = self.top()?;
+        self.discard(1);
+        Ok(value)
+    }
+
+    /// Returns the value on top of the stack without removing it.
+    /// Returns an error if the stack is empty.
+    fn top(&self) -> VmResult<Value> {
+        self.operand(0)
+    }
+
+    /// Returns the value the given number of slots below the top of the
+    /// stack, without removing it. Returns an error if the stack does not
+    /// hold that many values.
+    ///
+    /// Instructions read their operands with this, and only remove them
+    /// once every check has passed, so that a Fault holds the stack as it
+    /// was when the failing instruction started.
+    fn operand(&self, depth: usize) -> VmResult<Value> {
+        match self.stack.len().checked_sub(depth + 1) {
+            Some(slot) if slot >= self.floor => Ok(self.stack[slot]),
+            _ => Err(VmError::StackUnderflow(self.fault()))
+        }
+    }
+
+    /// Returns the integer operand at the given depth. Returns an error
+    /// if the stack is too short, or the value is not an integer.
+    fn int_operand(&self, depth: usize) -> VmResult<i64> {
+        match self.operand(depth)? {
+            Value::Int(val) => Ok(val),
+            value => Err(self.type_error(ValueKind::Int, value))
+        }
+    }
+
+    /// Returns the float operand at the given depth. Returns an error
+    /// if the stack is too short, or the value is not a float.
+    fn float_operand(&self, depth: usize) -> VmResult<f64> {
+        match self.operand(depth)? {
+            Value::Float(val) => Ok(val),
+            value => Err(self.type_error(ValueKind::Float, value))
+        }
+    }
+
+    /// Returns the heap address operand at the given depth. Returns an
+    /// error if the stack is too short, or the value is not an address.
+    fn addr_operand(&self, depth: usize) -> VmResult<usize> {
+        match self.operand(depth)? {
+            Value::Addr(addr) => Ok(addr),
+            value => Err(self.type_error(ValueKind::Addr, value))
+        }
+    }
+
+    /// Removes the given number of operands from the top of the stack.
+    /// The operands must already have been read with operand.
+    fn discard(&mut self, count: usize) {
+        let len = self.stack.len();
+        self.stack.truncate(len - count);
+    }
+
+    /// Replaces the given number of operands on top of the stack with
+    /// the result of an instruction.
+    fn replace(&mut self, count: usize, value: Value) -> VmResult<()> {
+        self.discard(count);
+        self.push(value)
+    }
+
+    /// Picks the result of an integer operation according to the current
+    /// overflow mode. The checked result is None when the operation
+    /// overflowed, which is an error in checked mode.
+    fn int_result(&self, checked: Option<i64>, wrapping: i64, saturating: i64) -> VmResult<Value> {
+        let result = match self.overflow {
+            OverflowMode::Wrapping => wrapping,
+            OverflowMode::Saturating => saturating,
+            OverflowMode::Checked => match checked {
+                Some(val) => val,
+                None => return Err(VmError::IntegerOverflow(self.fault()))
+            }
+        };
+
+        Ok(Value::Int(result))
+    }
+
+    /// Builds the error raised when an operation is given the wrong
+    /// kind of value.
+    fn type_error(&self, expected: ValueKind, found: Value) -> VmError {
+        VmError::TypeMismatch(expected, found, self.fault())
+    }
+
+    /// Moves a heap address by an offset, as is done when adding an
+    /// integer to an address. Returns an error if the resulting address
+    /// would be negative.
+    fn offset_addr(&self, addr: usize, offset: i64) -> VmResult<Value> {
+        let target = (addr as i64).wrapping_add(offset);
+
+        match util::maybe_i64_to_usize(target) {
+            Some(new_addr) => Ok(Value::Addr(new_addr)),
+            None => Err(VmError::IllegalAddress(target, self.fault()))
+        }
+    }
+
+    /// Converts a value taken from the stack into a memory address,
+    /// returning an error if it falls outside of memory.
+    fn mem_addr(&self, value: i64) -> VmResult<usize> {
+        match util::maybe_i64_to_usize(value) {
+            Some(addr) if addr < self.memory.len() => Ok(addr),
+            _ => Err(VmError::IllegalAddress(value, self.fault()))
+        }
+    }
+
+    /// Loads a constant on to the stack.
+    ///
+    /// By calling:
+    ///
+    /// LOADC 5
+    ///
+    /// The stack will look like:
+    ///
+    /// | 5 | <-- sp, and bottom of stack
+    /// +---+
+    fn loadc(&mut self, value: i64) -> VmResult<()> {
+        self.push(Value::Int(value))
+    }
+
+    /// Adds the top two numbers on the stack, and returns the
+    /// result on the top of the stack.
+    ///
+    /// Consider the following sequence of operations:
+    ///
+    /// LOADC 5
+    /// LOADC 5
+    /// ADD
+    ///
+    /// After execution, the stack will look like the following:
+    ///
+    /// | 10 | <-- sp, and bottom of stack
+    /// +----+
+    ///
+    /// An integer can also be added to a heap address, which offsets
+    /// the address and leaves an address on top of the stack.
+    fn add(&mut self) -> VmResult<()> {
+        let (a, b) = (self.operand(0)?, self.operand(1)?);
+        let result = match (a, b) {
+            (Value::Int(x), Value::Int(y)) => {
+                self.int_result(x.checked_add(y), x.wrapping_add(y), x.saturating_add(y))?
+            },
+            (Value::Addr(addr), Value::Int(offset)) |
+            (Value::Int(offset), Value::Addr(addr)) => self.offset_addr(addr, offset)?,
+            (Value::Int(_), other) | (other, _) => return Err(self.type_error(ValueKind::Int, other))
+        };
+
+        self.replace(2, result)
+    }
+
+    /// Multiplies the top two numbers on the stack, and returns the
+    /// result on the top of the stack.
+    fn mul(&mut self) -> VmResult<()> {
+        let (a, b) = (self.int_operand(0)?, self.int_operand(1)?);
+        let result = self.int_result(a.checked_mul(b), a.wrapping_mul(b), a.saturating_mul(b))?;
+
+        self.replace(2, result)
+    }
+
+    /// Subtracts the top two numbers on the stack, and returns the
+    /// result on the top of the stack. When the top of the stack is a
+    /// heap address, the integer below it is subtracted from the address.
+    fn sub(&mut self) -> VmResult<()> {
+        let (a, b) = (self.operand(0)?, self.operand(1)?);
+        let result = match (a, b) {
+            (Value::Int(x), Value::Int(y)) => {
+                self.int_result(x.checked_sub(y), x.wrapping_sub(y), x.saturating_sub(y))?
+            },
+            (Value::Addr(addr), Value::Int(offset)) => self.offset_addr(addr, offset.wrapping_neg())?,
+            (Value::Int(_), other) | (other, _) => return Err(self.type_error(ValueKind::Int, other))
+        };
+
+        self.replace(2, result)
+    }
+
+    /// Divides the top two numbers on the stack, and returns the
+    /// result on the top of the stack. The only division that overflows
+    /// is the minimum integer divided by -1. Dividing by zero raises a
+    /// DivisionByZero error, which can be caught with a trap handler.
+    fn div(&mut self) -> VmResult<()> {
+        let (a, b) = (self.int_operand(0)?, self.int_operand(1)?);
+        if b == 0 {
+            return Err(VmError::DivisionByZero(self.fault()));
+        }
+
+        let result = self.int_result(a.checked_div(b), a.wrapping_div(b), a.saturating_div(b))?;
+
+        self.replace(2, result)
+    }
+
+    /// Mods the top two numbers on the stack, and returns the
+    /// result on the top of the stack. This never overflows: the
+    /// minimum integer mod -1 is 0 in every overflow mode. Like DIV, a
+    /// divisor of zero raises a DivisionByZero error.
+    fn modq(&mut self) -> VmResult<()> {
+        let (a, b) = (self.int_operand(0)?, self.int_operand(1)?);
+        if b == 0 {
+            return Err(VmError::DivisionByZero(self.fault()));
+        }
+
+        self.replace(2, Value::Int(a.wrapping_rem(b)))
+    }
+
+    /// Performs a bitwise AND on the top two numbers on the stack,
+    /// and returns the result on the top of the stack. When both values
+    /// are booleans, a logical AND is performed instead.
+    fn and(&mut self) -> VmResult<()> {
+        let (a, b) = (self.operand(0)?, self.operand(1)?);
+        let result = match (a, b) {
+            (Value::Int(x), Value::Int(y)) => Value::Int(x & y),
+            (Value::Bool(x), Value::Bool(y)) => Value::Bool(x && y),
+            _ => return Err(self.logic_error(a, b))
+        };
+
+        self.replace(2, result)
+    }
+
+    /// Performs a bitwise OR on the top two numbers on the stack,
+    /// and returns the result on the top of the stack. When both values
+    /// are booleans, a logical OR is performed instead.
+    fn or(&mut self) -> VmResult<()> {
+        let (a, b) = (self.operand(0)?, self.operand(1)?);
+        let result = match (a, b) {
+            (Value::Int(x), Value::Int(y)) => Value::Int(x | y),
+            (Value::Bool(x), Value::Bool(y)) => Value::Bool(x || y),
+            _ => return Err(self.logic_error(a, b))
+        };
+
+        self.replace(2, result)
+    }
+
+    /// Compares the top two values on the stack, and places an integer on
+    /// top of the stack holding the result: 1 if the comparison holds and
+    /// 0 if it does not, so the result can be used in arithmetic. Operands are taken in the same
+    /// order as the arithmetic instructions: the top of the stack is on the
+    /// left hand side of the comparison.
+    ///
+    /// Consider the following sequence of operations:
+    ///
+    /// LOADC 5
+    /// LOADC 3
+    /// LT
+    ///
+    /// After execution, the stack will look like the following:
+    ///
+    /// | 1 | <-- 3 < 5, sp, and bottom of stack
+    /// +---+
+    fn cmp(&mut self, cmp: Cmp) -> VmResult<()> {
+        let result = self.compare(cmp)?;
+        self.replace(2, Value::Int(result as i64))
+    }
+
+    /// Compares the top two values on the stack, leaving them in place.
+    ///
+    /// Values can only be compared to values of the same kind. Any two
+    /// such values can be tested for equality, while only integers, floats
+    /// and addresses can be ordered. Floats follow IEEE 754, so NaN is not
+    /// equal to, less than or greater than any value, including itself.
+    fn compare(&self, cmp: Cmp) -> VmResult<bool> {
+        let (a, b) = (self.operand(0)?, self.operand(1)?);
+        let ordering = match (a, b) {
+            (Value::Int(x), Value::Int(y)) => x.partial_cmp(&y),
+            (Value::Float(x), Value::Float(y)) => x.partial_cmp(&y),
+            (Value::Addr(x), Value::Addr(y)) => x.partial_cmp(&y),
+            (Value::Bool(x), Value::Bool(y)) => match cmp {
+                Cmp::Eq | Cmp::Ne => x.partial_cmp(&y),
+                _ => return Err(self.type_error(ValueKind::Int, a))
+            },
+            _ => return Err(self.type_error(a.kind(), b))
+        };
+
+        let result = match (cmp, ordering) {
+            (Cmp::Ne, ordering) => ordering != Some(Ordering::Equal),
+            (_, None) => false,
+            (Cmp::Eq, Some(ord)) => ord == Ordering::Equal,
+            (Cmp::Lt, Some(ord)) => ord == Ordering::Less,
+            (Cmp::Le, Some(ord)) => ord != Ordering::Greater,
+            (Cmp::Gt, Some(ord)) => ord == Ordering::Greater,
+            (Cmp::Ge, Some(ord)) => ord != Ordering::Less
+        };
+
+        Ok(result)
+    }
+
+    /// Builds the error raised when AND or OR is given values that are
+    /// not both integers or both booleans.
+    fn logic_error(&self, a: Value, b: Value) -> VmError {
+        match a {
+            Value::Int(_) | Value::Bool(_) => self.type_error(a.kind(), b),
+            _ => self.type_error(ValueKind::Int, a)
+        }
+    }
+
+    /// Negates the top value on the stack, while keeping sp the same.
+    ///
+    /// Consider the following sequense of operations:
+    ///
+    /// LOADC 5
+    /// NEG
+    ///
+    /// After exection, the stack will look like the following:
+    ///
+    /// | -5 | <-- sp, and bottom of stack
+    /// +----+
+    fn neg(&mut self) -> VmResult<()> {
+        let a = self.int_operand(0)?;
+        let result = self.int_result(a.checked_neg(), a.wrapping_neg(), a.saturating_neg())?;
+
+        self.replace(1, result)
+    }
+
+    /// Loads a floating point constant on to the stack.
+    ///
+    /// Float instructions follow IEEE 754 semantics, and never raise
+    /// an error for the values they produce. Dividing by zero gives an
+    /// infinity (or NaN for 0.0 / 0.0), and any operation involving NaN
+    /// gives NaN. Float instructions only accept floats; integers must
+    /// first be converted with ITOF.
+    fn loadf(&mut self, value: f64) -> VmResult<()> {
+        self.push(Value::Float(value))
+    }
+
+    /// Adds the top two floats on the stack, and returns the
+    /// result on the top of the stack.
+    fn fadd(&mut self) -> VmResult<()> {
+        let (a, b) = (self.float_operand(0)?, self.float_operand(1)?);
+        self.replace(2, Value::Float(a + b))
+    }
+
+    /// Subtracts the top two floats on the stack, and returns the
+    /// result on the top of the stack. The operands are taken in the
+    /// same order as SUB.
+    fn fsub(&mut self) -> VmResult<()> {
+        let (a, b) = (self.float_operand(0)?, self.float_operand(1)?);
+        self.replace(2, Value::Float(a - b))
+    }
+
+    /// Multiplies the top two floats on the stack, and returns the
+    /// result on the top of the stack.
+    fn fmul(&mut self) -> VmResult<()> {
+        let (a, b) = (self.float_operand(0)?, self.float_operand(1)?);
+        self.replace(2, Value::Float(a * b))
+    }
+
+    /// Divides the top two floats on the stack, and returns the
+    /// result on the top of the stack. The operands are taken in the
+    /// same order as DIV. Division by zero is not an error, see LOADF.
+    fn fdiv(&mut self) -> VmResult<()> {
+        let (a, b) = (self.float_operand(0)?, self.float_operand(1)?);
+        self.replace(2, Value::Float(a / b))
+    }
+
+    /// Converts the integer on top of the stack into a float. Integers
+    /// too large to be represented exactly are rounded to the nearest float.
+    fn itof(&mut self) -> VmResult<()> {
+        let a = self.int_operand(0)?;
+        self.replace(1, Value::Float(a as f64))
+    }
+
+    /// Converts the float on top of the stack into an integer, rounding
+    /// towards zero. Returns an error if the float is NaN, an infinity,
+    /// or is outside of the range of a 64-bit integer.
+    fn ftoi(&mut self) -> VmResult<()> {
+        let a = self.float_operand(0)?;
+        // i64::MAX as f64 rounds up to 2^63, which is itself out of range.
+        if a.is_nan() || a < i64::MIN as f64 || a >= i64::MAX as f64 {
+            return Err(VmError::InvalidConversion(a, self.fault()));
+        }
+
+        self.replace(1, Value::Int(a.trunc() as i64))
+    }
+
+    /// Loads the value at an address in memory to the top of the stack.
+    /// Requires a single argument on top of the stack, corresponding to
+    /// the memory address to load. The address is popped, and the contents
+    /// of memory located at that address are placed on the top of the stack.
+    ///
+    /// Consider the following sequence of operations, where memory
+    /// address 1 holds the value 5:
+    ///
+    /// LOADC 6
+    /// LOADC 1 // this is the memory address we want to load.
+    ///
+    /// The stack now looks like this:
+    ///
+    /// | 1 | <-- address to load, and sp
+    /// | 6 | <-- bottom of stack
+    /// +---+
+    ///
+    /// Now, we can call the load instruction:
+    ///
+    /// LOAD
+    ///
+    /// And the stack will look like the following:
+    ///
+    /// | 5 | <-- loaded value 5 from address 1
+    /// | 6 | <-- bottom of stack
+    /// +---+
+    fn load(&mut self) -> VmResult<()> {
+        let load_loc = self.int_operand(0)?;
+        let addr = self.mem_addr(load_loc)?;
+        let value = self.memory[addr];
+
+        self.replace(1, value)
+    }
+
+    /// Convenience method provided so that we can generate
+    /// LOADV i64
+    ///
+    /// instead of
+    ///
+    /// LOADC i64
+    /// LOAD
+    fn loadv(&mut self, val: i64) -> VmResult<()> {
+        self.loadc(val)?;
+        self.load()
+    }
+
+    /// Stores a value at a specified address in memory. This function
+    /// expects two arguments on the stack: the top value should
+    /// be the memory address of where the value will be stored, and the second
+    /// value should be the actual number to store. After the number is
+    /// stored, the address is popped from the stack, leaving the stored
+    /// value on top of the stack.
+    ///
+    /// Consider the following sequence of operations:
+    ///
+    /// LOADC 6 // value to store
+    /// LOADC 1 // memory address to put it in
+    ///
+    /// Now, when we call store, we can put our value in the right spot in memory:
+    ///
+    /// STORE
+    ///
+    /// | 6 | <- sp, and bottom of stack
+    /// +---+
+    ///
+    /// Memory address 1 now holds the value 6.
+    fn store(&mut self) -> VmResult<()> {
+        let store_loc = self.int_operand(0)?;
+        let value = self.operand(1)?;
+        let addr = self.mem_addr(store_loc)?;
+        self.discard(1);
+        self.memory[addr] = value;
+        self.written(Watchpoint::Memory(addr));
+
+        Ok(())
+    }
+
+    /// Convenience method provided so that we can generate
+    /// STOREV i64
+    ///
+    /// instead of
+    ///
+    /// LOADC i64
+    /// STORE
+    fn storev(&mut self, val: i64) -> VmResult<()> {
+        self.loadc(val)?;
+        self.store()
+    }
+
+    /// Jumps to a location in the program, by returning the address the
+    /// program counter should move to. The argument given must be a string
+    /// that corresponds to a label provided in the program.
+    /// Returns an error if the label provided does not exist.
+    fn jmp(&self, loc: &str) -> VmResult<usize> {
+        match self.program.label(loc) {
+            Some(addr) => Ok(addr),
+            None => Err(VmError::UndefinedLabel(loc.to_string(), self.fault()))
+        }
+    }
+
+    /// Performs a jmp instruction, if the argument on the top of the stack is
+    /// a zero or false. If the value at sp is not zero, program execution
+    /// continues. In both cases the top of the stack is popped.
+    fn jmpz(&mut self, loc: &str) -> VmResult<Flow> {
+        let is_zero = !self.truth_operand()?;
+        self.jmp_if(is_zero, loc, 1)
+    }
+
+    /// Performs a jmp instruction, if the argument on the top of the stack is
+    /// not zero or is true. This is the opposite of jmpz, and also pops the
+    /// top of the stack.
+    fn jmpnz(&mut self, loc: &str) -> VmResult<Flow> {
+        let is_truthy = self.truth_operand()?;
+        self.jmp_if(is_truthy, loc, 1)
+    }
+
+    /// Compares the top two values on the stack in the same way as the
+    /// EQ family of instructions, and performs a jmp instruction if the
+    /// comparison holds. Both values are popped.
+    ///
+    /// Consider the following sequence of operations:
+    ///
+    /// LOADC 5
+    /// LOADC 3
+    /// JLT label
+    ///
+    /// This jumps to label, because 3 (the top of the stack) is less than 5.
+    fn jmp_cmp(&mut self, cmp: Cmp, loc: &str) -> VmResult<Flow> {
+        let holds = self.compare(cmp)?;
+        self.jmp_if(holds, loc, 2)
+    }
+
+    /// Jumps to the given label when the condition is true, otherwise
+    /// continues on to the next instruction. Either way, the given number
+    /// of operands the condition was computed from are popped, once the
+    /// label is known to exist.
+    fn jmp_if(&mut self, cond: bool, loc: &str, operands: usize) -> VmResult<Flow> {
+        let flow = if cond {
+            Flow::Jump(self.jmp(loc)?)
+        } else {
+            Flow::Next
+        };
+
+        self.discard(operands);
+        Ok(flow)
+    }
+
+    /// Returns whether the value on top of the stack is a true value.
+    /// Zero and false are false, every other integer or boolean is true.
+    fn truth_operand(&self) -> VmResult<bool> {
+        match self.operand(0)? {
+            Value::Int(val) => Ok(val != 0),
+            Value::Bool(val) => Ok(val),
+            value => Err(self.type_error(ValueKind::Int, value))
+        }
+    }
+
+    /// Performs and indexed jump. This function expects a single argument on top
+    /// of the stack, an address to jump to. Then, we add the offset provided
+    /// to that address and set the program counter.
+    fn jmpi(&mut self, offset: i64) -> VmResult<usize> {
+        let base = self.int_operand(0)?;
+        let target = base.wrapping_add(offset);
+
+        match util::maybe_i64_to_usize(target) {
+            Some(addr) if addr <= self.program.len() => {
+                self.discard(1);
+                Ok(addr)
+            },
+            _ => Err(VmError::IllegalJump(target, self.fault()))
+        }
+    }
+
+    /// Calls the subroutine starting at the given label. The address of the
+    /// instruction following the CALL is saved on the call stack, so that a
+    /// RET instruction can resume execution from there.
+    ///
+    /// Consider the following program:
+    ///
+    /// LOADC 5
+    /// CALL double
+    /// HALT
+    /// double:
+    /// DUP
+    /// ADD
+    /// RET
+    ///
+    /// After execution, the top of the stack will hold 10.
+    fn call(&mut self, loc: &str) -> VmResult<usize> {
+        let addr = self.jmp(loc)?;
+        self.call_addr(addr)
+    }
+
+    /// Saves the address of the instruction following the current one on
+    /// the call stack, and returns the address to jump to. Returns an error
+    /// if the call stack is full.
+    fn call_addr(&mut self, addr: usize) -> VmResult<usize> {
+        let max_depth = self.config.get_call_depth();
+        if self.call_stack.len() >= max_depth {
+            return Err(VmError::CallStackOverflow(max_depth, self.fault()));
+        }
+
+        self.call_stack.push(self.pc + 1);
+
+        Ok(addr)
+    }
+
+    /// Calls the host function registered under the given name. Its
+    /// arguments are popped from the stack once it returns successfully,
+    /// and its results pushed in their place. If it fails, the arguments
+    /// are left on the stack.
+    fn calln(&mut self, name: &str) -> VmResult<()> {
+        let function = match self.host.get_mut(name) {
+            Some(function) => function,
+            None => return Err(VmError::UndefinedFunction(name.to_string(), self.fault()))
+        };
+        if self.stack.len() - self.floor < function.arity {
+            return Err(VmError::StackUnderflow(self.fault()));
+        }
+
+        let at = self.stack.len() - function.arity;
+        let returns = function.returns;
+        let result = (function.func)(&self.stack[at..]);
+
+        let values = match result {
+            Ok(values) => values,
+            Err(message) => return Err(VmError::Host(name.to_string(), message, self.fault()))
+        };
+        if values.len() != returns {
+            let message = format!("returned {} values, expected {}", values.len(), returns);
+            return Err(VmError::Host(name.to_string(), message, self.fault()));
+        }
+
+        self.stack.truncate(at);
+        for value in values {
+            self.push(value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Installs a trap handler. Once installed, a division by zero no longer
+    /// stops the program. Instead, the handler is called as if by a CALL
+    /// placed in the failing instruction, with the operands of the division
+    /// already popped. A RET from the handler continues after the failing
+    /// instruction, so the handler can push a replacement result and return.
+    ///
+    /// TRAP on_zero
+    /// LOADC 0
+    /// LOADC 5
+    /// DIV
+    /// HALT
+    /// on_zero:
+    /// LOADC -1
+    /// RET
+    ///
+    /// Running this leaves -1 on top of the stack. Another TRAP replaces
+    /// the installed handler.
+    fn enter_trap(&mut self) -> VmResult<Flow> {
+        match self.trap {
+            Some(addr) => {
+                let flow = self.call_addr(addr).map(Flow::Jump)?;
+                self.discard(2);
+                Ok(flow)
+            },
+            None => Ok(Flow::Next)
+        }
+    }
+
+    /// Returns from the current subroutine, by popping the return
+    /// address off of the call stack.
+    fn ret(&mut self) -> VmResult<usize> {
+        match self.call_stack.pop() {
+            Some(addr) => Ok(addr),
+            None => Err(VmError::CallStackUnderflow(self.fault()))
+        }
+    }
+
+    /// Allocates a block of heap memory, and places the address of its
+    /// first cell on top of the stack. Every cell in the block starts
+    /// out as zero. The address can be offset with ADD to reach the
+    /// other cells in the block.
+    ///
+    /// ALLOC 2
+    ///
+    /// | addr | <-- sp, and bottom of stack
+    /// +------+
+    fn alloc(&mut self, size: i64) -> VmResult<()> {
+        let addr = self.heap.alloc(size).map_err(|e| self.heap_fault(e))?;
+        self.push(Value::Addr(addr))
+    }
+
+    /// Frees the heap block whose address is on top of the stack,
+    /// popping the address.
+    fn free(&mut self) -> VmResult<()> {
+        let addr = self.addr_operand(0)?;
+        self.heap.free(addr).map_err(|e| self.heap_fault(e))?;
+        self.discard(1);
+
+        Ok(())
+    }
+
+    /// Loads the value at the heap address on top of the stack. The
+    /// address is replaced by the loaded value, in the same way as LOAD.
+    fn loadh(&mut self) -> VmResult<()> {
+        let addr = self.addr_operand(0)?;
+        let value = self.heap.load(addr).map_err(|e| self.heap_fault(e))?;
+
+        self.replace(1, value)
+    }
+
+    /// Stores a value at a heap address. This expects the address on top
+    /// of the stack, and the value to store below it. In the same way as
+    /// STORE, the address is popped and the value is left on the stack.
+    fn storeh(&mut self) -> VmResult<()> {
+        let addr = self.addr_operand(0)?;
+        let value = self.operand(1)?;
+
+        self.heap.store(addr, value).map_err(|e| self.heap_fault(e))?;
+        self.discard(1);
+
+        Ok(())
+    }
+
+    /// Wraps an error from the heap into a VmError.
+    fn heap_fault(&self, error: HeapError) -> VmError {
+        VmError::Heap(error, self.fault())
+    }
+
+    /// Writes a message to stdout, followed by a newline.
+    fn print(&mut self, message: &str) -> VmResult<()> {
+        writeln!(self.io.stdout(), "{}", message).map_err(|e| self.io_fault(e))
+    }
+
+    /// Pops the value on top of the stack and writes it to stdout,
+    /// followed by a newline.
+    fn printv(&mut self) -> VmResult<()> {
+        let value = self.top()?;
+        writeln!(self.io.stdout(), "{}", value).map_err(|e| self.io_fault(e))?;
+        self.discard(1);
+
+        Ok(())
+    }
+
+    /// Pops one value for each placeholder in the template, and writes
+    /// the filled in template to stdout, followed by a newline. The
+    /// values fill the placeholders in the order they were pushed, so
+    /// the top of the stack fills the last placeholder.
+    ///
+    /// LOADC 3
+    /// LOADC 255
+    /// PRINTF x={} y={:x}
+    ///
+    /// This prints "x=3 y=ff". Hex and binary placeholders require
+    /// integers.
+    fn printf(&mut self, template: &Template) -> VmResult<()> {
+        let count = template.arity();
+        self.require(count)?;
+
+        let at = self.stack.len() - count;
+        let text = match template.render(&self.stack[at..]) {
+            Ok(text) => text,
+            Err((expected, found)) => return Err(self.type_error(expected, found))
+        };
+
+        writeln!(self.io.stdout(), "{}", text).map_err(|e| self.io_fault(e))?;
+        self.discard(count);
+
+        Ok(())
+    }
+
+    /// Writes a message to stderr, followed by a newline.
+    fn eprint(&mut self, message: &str) -> VmResult<()> {
+        writeln!(self.io.stderr(), "{}", message).map_err(|e| self.io_fault(e))
+    }
+
+    /// Reads a line from stdin and pushes the integer it holds. Whitespace
+    /// around the number is ignored. Anything written to stdout is flushed
+    /// first, so that a prompt printed before READ is seen.
+    ///
+    /// PRINT Enter a number:
+    /// READ
+    ///
+    /// Returns an error if no input is left, or the line is not an integer.
+    fn read(&mut self) -> VmResult<()> {
+        self.io.stdout().flush().map_err(|e| self.io_fault(e))?;
+
+        let mut line = String::new();
+        let count = self.io.stdin().read_line(&mut line).map_err(|e| self.io_fault(e))?;
+        if count == 0 {
+            return Err(VmError::EndOfInput(self.fault()));
+        }
+
+        let input = line.trim();
+        match input.parse::<i64>() {
+            Ok(val) => self.push(Value::Int(val)),
+            Err(_) => Err(VmError::InvalidInput(input.to_string(), self.fault()))
+        }
+    }
+
+    /// Wraps an error from one of the I/O streams into a VmError.
+    fn io_fault(&self, error: io::Error) -> VmError {
+        VmError::Io(error.kind(), self.fault())
+    }
+
+    /// Duplicate the top value on the stack.
+    fn dup(&mut self) -> VmResult<()> {
+        let value = self.top()?;
+        self.push(value)
+    }
+
+    /// Exchange the top two values on the stack.
+    fn swap(&mut self) -> VmResult<()> {
+        self.require(2)?;
+
+        let len = self.stack.len();
+        self.stack.swap(len - 1, len - 2);
+        self.written(Watchpoint::Stack(len - 2));
+        self.written(Watchpoint::Stack(len - 1));
+        Ok(())
+    }
+
+    /// Push a copy of the second value on the stack. For example, a stack
+    /// holding 1 2, with 2 on top, holds 1 2 1 afterwards.
+    fn over(&mut self) -> VmResult<()> {
+        self.pick(1)
+    }
+
+    /// Move the third value on the stack to the top. For example, a stack
+    /// holding 1 2 3, with 3 on top, holds 2 3 1 afterwards.
+    fn rot(&mut self) -> VmResult<()> {
+        self.require(3)?;
+
+        let len = self.stack.len();
+        let value = self.stack.remove(len - 3);
+        self.stack.push(value);
+        for slot in len - 3..len {
+            self.written(Watchpoint::Stack(slot));
+        }
+        Ok(())
+    }
+
+    /// Push a copy of the value n slots below the top of the stack.
+    /// PICK 0 is the same as DUP, and PICK 1 the same as OVER. Returns
+    /// a stack underflow error if the stack does not hold n + 1 values.
+    fn pick(&mut self, n: i64) -> VmResult<()> {
+        let depth = match util::maybe_i64_to_usize(n) {
+            Some(n) if n < self.stack.len() - self.floor => n,
+            _ => return Err(VmError::StackUnderflow(self.fault()))
+        };
+
+        let value = self.stack[self.stack.len() - 1 - depth];
+        self.push(value)
+    }
+
+    /// Push the number of values on the stack, not counting the pushed
+    /// value itself, or values below the floor set by invoke.
+    fn depth(&mut self) -> VmResult<()> {
+        let depth = (self.stack.len() - self.floor) as i64;
+        self.push(Value::Int(depth))
+    }
+
+    /// Records a write to a location, so that the run pauses once the
+    /// current instruction is done if the location is being watched.
+    fn written(&mut self, location: Watchpoint) {
+        if self.watch_hit.is_none() && self.watchpoints.contains(&location) {
+            self.watch_hit = Some(location);
+        }
+    }
+
+    /// Check that the stack holds at least the given number of values,
+    /// returning a stack underflow error if it does not.
+    fn require(&self, count: usize) -> VmResult<()> {
+        if self.stack.len() - self.floor < count {
+            return Err(VmError::StackUnderflow(self.fault()));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{OverflowMode, VmConfig};
+    use debug::{Breakpoint, Pause, Watchpoint};
+    use format::Template;
+    use io::MemoryIo;
+    use op::OpCode;
+    use profile::Profiler;
+    use snapshot::SnapshotError;
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+    use program::Program;
+    use sym_tab::SymbolTable;
+    use trace::Tracer;
+
+    #[test]
+    fn test_run_loadc() {
+        let prog = vec![OpCode::LOADC(5)];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(5)));
+    }
+
+    #[test]
+    fn test_run_add() {
+        let prog = vec![OpCode::LOADC(5), OpCode::LOADC(5), OpCode::ADD];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(10)));
+    }
+
+    #[test]
+    fn test_run_sub() {
+        let prog = vec![OpCode::LOADC(5), OpCode::LOADC(4), OpCode::SUB];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(-1)));
+    }
+
+    #[test]
+    fn test_run_mul() {
+        let prog = vec![OpCode::LOADC(5), OpCode::LOADC(5), OpCode::MUL];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(25)));
+    }
+
+    #[test]
+    fn test_run_div() {
+        let prog = vec![OpCode::LOADC(5), OpCode::LOADC(5), OpCode::DIV];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(1)));
+    }
+
+    #[test]
+    fn test_run_div_with_remainder() {
+        let prog = vec![OpCode::LOADC(3), OpCode::LOADC(10), OpCode::DIV];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(3)));
+    }
+
+    #[test]
+    fn test_run_modq() {
+        let prog = vec![OpCode::LOADC(2), OpCode::LOADC(4), OpCode::MOD];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(0)));
+    }
+
+    #[test]
+    fn test_run_and() {
+        let prog = vec![OpCode::LOADC(2), OpCode::LOADC(2), OpCode::AND];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(2)));
+    }
+
+    #[test]
+    fn test_run_or() {
+        let prog = vec![OpCode::LOADC(3), OpCode::LOADC(2), OpCode::OR];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(3)));
+    }
+
+    #[test]
+    fn test_run_neg() {
+        let prog = vec![OpCode::LOADC(5), OpCode::NEG];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(-5)));
+    }
+
+    #[test]
+    fn test_run_load() {
+        let prog = vec![OpCode::LOADC(4), OpCode::LOADC(1), OpCode::LOAD];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.memory[1] = Value::Int(5);
+        vm.run().unwrap();
+
+        assert_eq!(vm.stack, vec![Value::Int(4), Value::Int(5)]);
+    }
+
+    #[test]
+    fn test_run_load_no_contents() {
+        let prog = vec![OpCode::LOADC(5), OpCode::LOADC(5), OpCode::LOADC(4), OpCode::LOAD];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+        // Load will return 0 if nothing has been stored at the memory address attempted to load.
+        assert_eq!(vm.peek(), Some(Value::Int(0)));
+    }
+
+    #[test]
+    fn test_run_load_illegal_value() {
+        let prog = vec![OpCode::LOADC(5), OpCode::LOADC(5), OpCode::LOADC(-2), OpCode::LOAD];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let expected = VmError::IllegalAddress(-2, Fault {
+            pc: 3,
+            line: 4,
+            op: Some(OpCode::LOAD),
+            stack: vec![Value::Int(5), Value::Int(5), Value::Int(-2)]
+        });
+
+        assert_eq!(vm.run(), Err(expected));
+    }
+
+    #[test]
+    fn test_run_loadv() {
+        let prog = vec![OpCode::LOADC(3), OpCode::STOREV(1), OpCode::LOADC(4), OpCode::LOADV(1)];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(3)));
+    }
+
+    #[test]
+    fn test_run_store() {
+        let prog = vec![OpCode::LOADC(5), OpCode::LOADC(4), OpCode::LOADC(1), OpCode::STORE];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(4)));
+        assert_eq!(vm.memory[1], Value::Int(4));
+    }
+
+    #[test]
+    fn test_run_store_illegal_value() {
+        let prog = vec![OpCode::LOADC(5), OpCode::LOADC(5), OpCode::LOADC(-1), OpCode::STORE];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.run();
+
+        match result {
+            Err(VmError::IllegalAddress(-1, _)) => {},
+            _ => panic!("expected illegal address error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_store_out_of_memory() {
+        let prog = vec![OpCode::LOADC(5), OpCode::STOREV(8)];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new().memory_size(8));
+        let result = vm.run();
+
+        match result {
+            Err(VmError::IllegalAddress(8, _)) => {},
+            _ => panic!("expected illegal address error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_store_keeps_stack_separate() {
+        let prog = vec![OpCode::LOADC(5), OpCode::LOADC(6), OpCode::STOREV(0)];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.stack, vec![Value::Int(5), Value::Int(6)]);
+        assert_eq!(vm.memory[0], Value::Int(6));
+    }
+
+    #[test]
+    fn test_run_storev() {
+        let prog = vec![OpCode::LOADC(5),  OpCode::STOREV(1)];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(5)));
+    }
+
+    #[test]
+    fn test_run_jmp() {
+        let prog = vec![
+            OpCode::LABEL("label1".to_string(), 1),
+            OpCode::LOADC(5),
+            OpCode::LOADC(6),
+            OpCode::JMP("halt".to_string()),
+            OpCode::LOADC(7),
+            OpCode::LABEL("halt".to_string(), 5)
+        ];
+        let mut sym_tab = SymbolTable::new();
+        sym_tab.insert("label1".to_string(), 1);
+        sym_tab.insert("halt".to_string(), 5);
+
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(6)));
+    }
+
+    #[test]
+    fn test_run_jmp_no_label() {
+        let prog = vec![
+            OpCode::LABEL("label1".to_string(), 1),
+            OpCode::LOADC(5),
+            OpCode::LOADC(6),
+            OpCode::JMP("label2".to_string())
+        ];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let expected = VmError::UndefinedLabel("label2".to_string(), Fault {
+            pc: 3,
+            line: 4,
+            op: Some(OpCode::JMP("label2".to_string())),
+            stack: vec![Value::Int(5), Value::Int(6)]
+        });
+
+        assert_eq!(vm.run(), Err(expected));
+    }
+
+    #[test]
+    fn test_run_jmpz() {
+        let prog = vec![
+            OpCode::LABEL("label1".to_string(), 1),
+            OpCode::LOADC(5),
+            OpCode::LOADC(6),
+            OpCode::LOADC(0),
+            OpCode::JMPZ("halt".to_string()),
+            OpCode::LABEL("halt".to_string(), 5),
+            OpCode::LOADC(7)
+        ];
+        let mut sym_tab = SymbolTable::new();
+        sym_tab.insert("label1".to_string(), 1);
+        sym_tab.insert("halt".to_string(), 5);
+
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(7)));
+    }
+
+    #[test]
+    fn test_run_jmpz_not_zero() {
+        let prog = vec![
+            OpCode::LABEL("label1".to_string(), 1),
+            OpCode::LOADC(5),
+            OpCode::LOADC(6),
+            OpCode::LOADC(1),
+            OpCode::JMPZ("label1".to_string())
+        ];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(6)));
+    }
+
+    #[test]
+    fn test_run_jmpi() {
+        let prog = vec![
+            OpCode::LOADC(5),
+            OpCode::LOADC(6),
+            OpCode::LOADC(1),
+            OpCode::JMPI(3),
+            OpCode::LOADC(4),
+            OpCode::HALT
+        ];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+
+        assert_eq!(vm.run(), Ok(ExitState::Halted));
+        assert_eq!(vm.peek(), Some(Value::Int(4)));
+    }
+
+    #[test]
+    fn test_run_jmpi_out_of_range() {
+        let prog = vec![OpCode::LOADC(1), OpCode::JMPI(10)];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.run();
+
+        match result {
+            Err(VmError::IllegalJump(11, _)) => {},
+            _ => panic!("expected illegal jump error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_dup() {
+        let prog = vec![OpCode::LOADC(5), OpCode::DUP];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(5)));
+    }
+
+    #[test]
+    fn test_run_swap() {
+        let prog = vec![OpCode::LOADC(1), OpCode::LOADC(2), OpCode::SWAP];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.stack, vec![Value::Int(2), Value::Int(1)]);
+    }
+
+    #[test]
+    fn test_run_swap_sub() {
+        let prog = vec![OpCode::LOADC(10), OpCode::LOADC(3), OpCode::SWAP, OpCode::SUB];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(7)));
+    }
+
+    #[test]
+    fn test_run_swap_underflow() {
+        let prog = vec![OpCode::LOADC(1), OpCode::SWAP];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.run();
+
+        match result {
+            Err(VmError::StackUnderflow(ref fault)) => assert_eq!(fault.stack, vec![Value::Int(1)]),
+            _ => panic!("expected stack underflow error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_drop() {
+        let prog = vec![OpCode::LOADC(1), OpCode::LOADC(2), OpCode::DROP];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.stack, vec![Value::Int(1)]);
+    }
+
+    #[test]
+    fn test_run_drop_underflow() {
+        let prog = vec![OpCode::DROP];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.run();
+
+        match result {
+            Err(VmError::StackUnderflow(_)) => {},
+            _ => panic!("expected stack underflow error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_over() {
+        let prog = vec![OpCode::LOADC(1), OpCode::LOADC(2), OpCode::OVER];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.stack, vec![Value::Int(1), Value::Int(2), Value::Int(1)]);
+    }
+
+    #[test]
+    fn test_run_over_underflow() {
+        let prog = vec![OpCode::LOADC(1), OpCode::OVER];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.run();
+
+        match result {
+            Err(VmError::StackUnderflow(_)) => {},
+            _ => panic!("expected stack underflow error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_rot() {
+        let prog = vec![OpCode::LOADC(1), OpCode::LOADC(2), OpCode::LOADC(3), OpCode::ROT];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.stack, vec![Value::Int(2), Value::Int(3), Value::Int(1)]);
+    }
+
+    #[test]
+    fn test_run_rot_underflow() {
+        let prog = vec![OpCode::LOADC(1), OpCode::LOADC(2), OpCode::ROT];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.run();
+
+        match result {
+            Err(VmError::StackUnderflow(ref fault)) => assert_eq!(fault.stack.len(), 2),
+            _ => panic!("expected stack underflow error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_pick() {
+        let prog = vec![OpCode::LOADC(1), OpCode::LOADC(2), OpCode::LOADC(3), OpCode::PICK(2)];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.stack, vec![Value::Int(1), Value::Int(2), Value::Int(3), Value::Int(1)]);
+    }
+
+    #[test]
+    fn test_run_pick_zero() {
+        let prog = vec![OpCode::LOADC(1), OpCode::LOADC(2), OpCode::PICK(0)];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.stack, vec![Value::Int(1), Value::Int(2), Value::Int(2)]);
+    }
+
+    #[test]
+    fn test_run_pick_underflow() {
+        let prog = vec![OpCode::LOADC(1), OpCode::LOADC(2), OpCode::PICK(2)];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.run();
+
+        match result {
+            Err(VmError::StackUnderflow(ref fault)) => assert_eq!(fault.pc, 2),
+            _ => panic!("expected stack underflow error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_pick_negative() {
+        let prog = vec![OpCode::LOADC(1), OpCode::PICK(-1)];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.run();
+
+        match result {
+            Err(VmError::StackUnderflow(_)) => {},
+            _ => panic!("expected stack underflow error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_depth() {
+        let prog = vec![OpCode::LOADC(7), OpCode::LOADC(8), OpCode::DEPTH];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(2)));
+    }
+
+    #[test]
+    fn test_run_depth_empty() {
+        let prog = vec![OpCode::DEPTH];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(0)));
+    }
+
+    #[test]
+    fn test_run_stack_underflow() {
+        let prog = vec![OpCode::LOADC(5), OpCode::ADD];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.run();
+
+        match result {
+            Err(VmError::StackUnderflow(ref fault)) => assert_eq!(fault.pc, 1),
+            _ => panic!("expected stack underflow error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_stack_overflow() {
+        let prog = vec![OpCode::LOADC(1); 11];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new().stack_size(10));
+        let result = vm.run();
+
+        match result {
+            Err(VmError::StackOverflow(_)) => {},
+            _ => panic!("expected stack overflow error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_stack_grows() {
+        let prog = vec![OpCode::LOADC(1); 10];
+        let sym_tab = SymbolTable::new();
+        let config = VmConfig::new().stack_size(4).max_stack_size(16).growable(true);
+        let mut vm = Vm::new(Program::new(prog, sym_tab), config);
+
+        assert_eq!(vm.run(), Ok(ExitState::Finished));
+        assert_eq!(vm.stack.len(), 10);
+    }
+
+    #[test]
+    fn test_run_stack_grows_to_limit() {
+        let prog = vec![OpCode::LOADC(1); 10];
+        let sym_tab = SymbolTable::new();
+        let config = VmConfig::new().stack_size(4).max_stack_size(8).growable(true);
+        let mut vm = Vm::new(Program::new(prog, sym_tab), config);
+        let result = vm.run();
+
+        match result {
+            Err(VmError::StackOverflow(ref fault)) => assert_eq!(fault.pc, 8),
+            _ => panic!("expected stack overflow error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_halt() {
+        let prog = vec![OpCode::LOADC(5), OpCode::HALT, OpCode::LOADC(6)];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+
+        assert_eq!(vm.run(), Ok(ExitState::Halted));
+        assert_eq!(vm.peek(), Some(Value::Int(5)));
+    }
+
+    #[test]
+    fn test_run_call() {
+        let prog = vec![
+            OpCode::LOADC(5),
+            OpCode::CALL("double".to_string()),
+            OpCode::LOADC(1),
+            OpCode::ADD,
+            OpCode::HALT,
+            OpCode::LABEL("double".to_string(), 5),
+            OpCode::DUP,
+            OpCode::ADD,
+            OpCode::RET
+        ];
+        let mut sym_tab = SymbolTable::new();
+        sym_tab.insert("double".to_string(), 5);
+
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+
+        assert_eq!(vm.run(), Ok(ExitState::Halted));
+        assert_eq!(vm.peek(), Some(Value::Int(11)));
+    }
+
+    #[test]
+    fn test_run_call_nested() {
+        let prog = vec![
+            OpCode::CALL("outer".to_string()),
+            OpCode::HALT,
+            OpCode::LABEL("outer".to_string(), 2),
+            OpCode::CALL("inner".to_string()),
+            OpCode::LOADC(2),
+            OpCode::MUL,
+            OpCode::RET,
+            OpCode::LABEL("inner".to_string(), 7),
+            OpCode::LOADC(3),
+            OpCode::RET
+        ];
+        let mut sym_tab = SymbolTable::new();
+        sym_tab.insert("outer".to_string(), 2);
+        sym_tab.insert("inner".to_string(), 7);
+
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+
+        assert_eq!(vm.run(), Ok(ExitState::Halted));
+        assert_eq!(vm.peek(), Some(Value::Int(6)));
+    }
+
+    #[test]
+    fn test_run_call_overflow() {
+        let prog = vec![
+            OpCode::LABEL("recurse".to_string(), 0),
+            OpCode::CALL("recurse".to_string())
+        ];
+        let mut sym_tab = SymbolTable::new();
+        sym_tab.insert("recurse".to_string(), 0);
+
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new().call_depth(4));
+        let result = vm.run();
+
+        match result {
+            Err(VmError::CallStackOverflow(4, ref fault)) => assert_eq!(fault.pc, 1),
+            _ => panic!("expected call stack overflow error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_ret_outside_call() {
+        let prog = vec![OpCode::LOADC(5), OpCode::RET];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let expected = VmError::CallStackUnderflow(Fault {
+            pc: 1,
+            line: 2,
+            op: Some(OpCode::RET),
+            stack: vec![Value::Int(5)]
+        });
+
+        assert_eq!(vm.run(), Err(expected));
+    }
+
+    #[test]
+    fn test_run_alloc_storeh_loadh() {
+        let prog = vec![
+            OpCode::ALLOC(2),
+            OpCode::STOREV(0),
+            OpCode::LOADC(7),
+            OpCode::LOADV(0),
+            OpCode::LOADC(1),
+            OpCode::ADD,
+            OpCode::STOREH,
+            OpCode::LOADV(0),
+            OpCode::LOADC(1),
+            OpCode::ADD,
+            OpCode::LOADH
+        ];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(7)));
+        assert_eq!(vm.heap().leaks().len(), 1);
+    }
+
+    #[test]
+    fn test_run_free() {
+        let prog = vec![OpCode::ALLOC(2), OpCode::FREE];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), None);
+        assert!(vm.heap().leaks().is_empty());
+    }
+
+    #[test]
+    fn test_run_double_free() {
+        let prog = vec![OpCode::ALLOC(2), OpCode::DUP, OpCode::FREE, OpCode::FREE];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.run();
+
+        match result {
+            Err(VmError::Heap(HeapError::DoubleFree(_), ref fault)) => assert_eq!(fault.pc, 3),
+            _ => panic!("expected double free error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_use_after_free() {
+        let prog = vec![OpCode::ALLOC(2), OpCode::DUP, OpCode::FREE, OpCode::LOADH];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.run();
+
+        match result {
+            Err(VmError::Heap(HeapError::UseAfterFree(_), ref fault)) => assert_eq!(fault.pc, 3),
+            _ => panic!("expected use after free error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_alloc_out_of_memory() {
+        let prog = vec![OpCode::ALLOC(4), OpCode::ALLOC(4)];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new().heap_size(6));
+        let result = vm.run();
+
+        match result {
+            Err(VmError::Heap(HeapError::OutOfMemory(4), ref fault)) => assert_eq!(fault.pc, 1),
+            _ => panic!("expected out of memory error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_add_addr_offset() {
+        let prog = vec![OpCode::ALLOC(4), OpCode::LOADC(3), OpCode::ADD];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Addr(4)));
+    }
+
+    #[test]
+    fn test_run_mul_addr() {
+        let prog = vec![OpCode::ALLOC(4), OpCode::LOADC(3), OpCode::MUL];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.run();
+
+        match result {
+            Err(VmError::TypeMismatch(ValueKind::Int, Value::Addr(1), ref fault)) => assert_eq!(fault.pc, 2),
+            _ => panic!("expected type mismatch error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_add_two_addrs() {
+        let prog = vec![OpCode::ALLOC(4), OpCode::ALLOC(4), OpCode::ADD];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.run();
+
+        match result {
+            Err(VmError::TypeMismatch(ValueKind::Int, Value::Addr(5), _)) => {},
+            _ => panic!("expected type mismatch error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_loadh_int() {
+        let prog = vec![OpCode::LOADC(1), OpCode::LOADH];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.run();
+
+        match result {
+            Err(VmError::TypeMismatch(ValueKind::Addr, Value::Int(1), _)) => {},
+            _ => panic!("expected type mismatch error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_and_bools() {
+        let prog = vec![OpCode::AND];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.stack = vec![Value::Bool(true), Value::Bool(false)];
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Bool(false)));
+    }
+
+    #[test]
+    fn test_run_or_mixed() {
+        let prog = vec![OpCode::LOADC(1), OpCode::OR];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.stack = vec![Value::Bool(true)];
+        let result = vm.run();
+
+        match result {
+            Err(VmError::TypeMismatch(ValueKind::Int, Value::Bool(true), _)) => {},
+            _ => panic!("expected type mismatch error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_jmpz_false() {
+        let prog = vec![
+            OpCode::JMPZ("end".to_string()),
+            OpCode::LOADC(1),
+            OpCode::LABEL("end".to_string(), 2)
+        ];
+        let mut sym_tab = SymbolTable::new();
+        sym_tab.insert("end".to_string(), 2);
+
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.stack = vec![Value::Bool(false)];
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), None);
+    }
+
+    #[test]
+    fn test_run_loadf() {
+        let prog = vec![OpCode::LOADF(1.5)];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Float(1.5)));
+    }
+
+    #[test]
+    fn test_run_fadd() {
+        let prog = vec![OpCode::LOADF(1.5), OpCode::LOADF(2.25), OpCode::FADD];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Float(3.75)));
+    }
+
+    #[test]
+    fn test_run_fsub() {
+        let prog = vec![OpCode::LOADF(5.0), OpCode::LOADF(4.5), OpCode::FSUB];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Float(-0.5)));
+    }
+
+    #[test]
+    fn test_run_fmul() {
+        let prog = vec![OpCode::LOADF(1.5), OpCode::LOADF(4.0), OpCode::FMUL];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Float(6.0)));
+    }
+
+    #[test]
+    fn test_run_fdiv() {
+        let prog = vec![OpCode::LOADF(4.0), OpCode::LOADF(1.0), OpCode::FDIV];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Float(0.25)));
+    }
+
+    #[test]
+    fn test_run_fdiv_by_zero() {
+        let prog = vec![OpCode::LOADF(0.0), OpCode::LOADF(1.0), OpCode::FDIV];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Float(f64::INFINITY)));
+    }
+
+    #[test]
+    fn test_run_fadd_int() {
+        let prog = vec![OpCode::LOADC(1), OpCode::LOADF(1.0), OpCode::FADD];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.run();
+
+        match result {
+            Err(VmError::TypeMismatch(ValueKind::Float, Value::Int(1), _)) => {},
+            _ => panic!("expected type mismatch error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_itof() {
+        let prog = vec![OpCode::LOADC(-3), OpCode::ITOF];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Float(-3.0)));
+    }
+
+    #[test]
+    fn test_run_ftoi() {
+        let prog = vec![OpCode::LOADF(-3.75), OpCode::FTOI];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(-3)));
+    }
+
+    #[test]
+    fn test_run_ftoi_nan() {
+        let prog = vec![OpCode::LOADF(f64::NAN), OpCode::FTOI];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.run();
+
+        match result {
+            Err(VmError::InvalidConversion(val, ref fault)) => {
+                assert!(val.is_nan());
+                assert_eq!(fault.pc, 1);
+            },
+            _ => panic!("expected invalid conversion error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_ftoi_out_of_range() {
+        let prog = vec![OpCode::LOADF(1e19), OpCode::FTOI];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.run();
+
+        match result {
+            Err(VmError::InvalidConversion(_, _)) => {},
+            _ => panic!("expected invalid conversion error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_eq() {
+        let prog = vec![OpCode::LOADC(5), OpCode::LOADC(5), OpCode::EQ];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(1)));
+    }
+
+    #[test]
+    fn test_run_ne() {
+        let prog = vec![OpCode::LOADC(5), OpCode::LOADC(5), OpCode::NE];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(0)));
+    }
+
+    #[test]
+    fn test_run_lt() {
+        let prog = vec![OpCode::LOADC(5), OpCode::LOADC(3), OpCode::LT];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(1)));
+    }
+
+    #[test]
+    fn test_run_le() {
+        let prog = vec![OpCode::LOADC(3), OpCode::LOADC(3), OpCode::LE];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(1)));
+    }
+
+    #[test]
+    fn test_run_gt() {
+        let prog = vec![OpCode::LOADC(5), OpCode::LOADC(3), OpCode::GT];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(0)));
+    }
+
+    #[test]
+    fn test_run_ge_floats() {
+        let prog = vec![OpCode::LOADF(1.5), OpCode::LOADF(2.5), OpCode::GE];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(1)));
+    }
+
+    #[test]
+    fn test_run_cmp_arithmetic() {
+        let prog = vec![
+            OpCode::LOADC(5),
+            OpCode::LOADC(3),
+            OpCode::LT,
+            OpCode::LOADC(1),
+            OpCode::LOADC(2),
+            OpCode::GT,
+            OpCode::ADD
+        ];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.stack, vec![Value::Int(2)]);
+    }
+
+    #[test]
+    fn test_run_nan_comparisons() {
+        let prog = vec![
+            OpCode::LOADF(f64::NAN),
+            OpCode::LOADF(f64::NAN),
+            OpCode::EQ,
+            OpCode::LOADF(f64::NAN),
+            OpCode::LOADF(f64::NAN),
+            OpCode::NE
+        ];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.stack, vec![Value::Int(0), Value::Int(1)]);
+    }
+
+    #[test]
+    fn test_run_cmp_mixed() {
+        let prog = vec![OpCode::LOADF(1.0), OpCode::LOADC(1), OpCode::EQ];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.run();
+
+        match result {
+            Err(VmError::TypeMismatch(ValueKind::Int, Value::Float(_), ref fault)) => assert_eq!(fault.pc, 2),
+            _ => panic!("expected type mismatch error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_jmpnz() {
+        let prog = vec![
+            OpCode::LOADC(4),
+            OpCode::JMPNZ("end".to_string()),
+            OpCode::LOADC(1),
+            OpCode::LABEL("end".to_string(), 3)
+        ];
+        let mut sym_tab = SymbolTable::new();
+        sym_tab.insert("end".to_string(), 3);
+
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), None);
+    }
+
+    #[test]
+    fn test_run_jlt_loop() {
+        // Counts memory address 0 up from 0, until it is no longer less than 3.
+        let prog = vec![
+            OpCode::LABEL("loop".to_string(), 0),
+            OpCode::LOADV(0),
+            OpCode::LOADC(1),
+            OpCode::ADD,
+            OpCode::STOREV(0),
+            OpCode::LOADC(3),
+            OpCode::LOADV(0),
+            OpCode::JLT("loop".to_string())
+        ];
+        let mut sym_tab = SymbolTable::new();
+        sym_tab.insert("loop".to_string(), 0);
+
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.memory[0], Value::Int(3));
+    }
+
+    #[test]
+    fn test_run_jge_not_taken() {
+        let prog = vec![
+            OpCode::LOADC(5),
+            OpCode::LOADC(3),
+            OpCode::JGE("end".to_string()),
+            OpCode::LOADC(1),
+            OpCode::LABEL("end".to_string(), 4)
+        ];
+        let mut sym_tab = SymbolTable::new();
+        sym_tab.insert("end".to_string(), 4);
+
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.stack, vec![Value::Int(1)]);
+    }
+
+    #[test]
+    fn test_run_add_overflow_wrapping() {
+        let prog = vec![OpCode::LOADC(1), OpCode::LOADC(i64::MAX), OpCode::ADD];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(i64::MIN)));
+    }
+
+    #[test]
+    fn test_run_add_overflow_checked() {
+        let prog = vec![OpCode::LOADC(1), OpCode::LOADC(i64::MAX), OpCode::ADD];
+        let sym_tab = SymbolTable::new();
+        let config = VmConfig::new().overflow(OverflowMode::Checked);
+        let mut vm = Vm::new(Program::new(prog, sym_tab), config);
+        let result = vm.run();
+
+        match result {
+            Err(VmError::IntegerOverflow(ref fault)) => assert_eq!(fault.pc, 2),
+            _ => panic!("expected integer overflow error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_mul_overflow_saturating() {
+        let prog = vec![OpCode::LOADC(-2), OpCode::LOADC(i64::MAX), OpCode::MUL];
+        let sym_tab = SymbolTable::new();
+        let config = VmConfig::new().overflow(OverflowMode::Saturating);
+        let mut vm = Vm::new(Program::new(prog, sym_tab), config);
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(i64::MIN)));
+    }
+
+    #[test]
+    fn test_run_div_overflow() {
+        let modes = vec![
+            (OverflowMode::Wrapping, Ok(Value::Int(i64::MIN))),
+            (OverflowMode::Saturating, Ok(Value::Int(i64::MAX))),
+            (OverflowMode::Checked, Err(()))
+        ];
+
+        for (mode, expected) in modes {
+            let prog = vec![OpCode::LOADC(-1), OpCode::LOADC(i64::MIN), OpCode::DIV];
+            let sym_tab = SymbolTable::new();
+            let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new().overflow(mode));
+            let result = vm.run().map(|_| vm.peek().unwrap()).map_err(|_| ());
+
+            assert_eq!(result, expected);
+        }
+    }
+
+    #[test]
+    fn test_run_mod_min_by_negative_one() {
+        let prog = vec![OpCode::LOADC(-1), OpCode::LOADC(i64::MIN), OpCode::MOD];
+        let sym_tab = SymbolTable::new();
+        let config = VmConfig::new().overflow(OverflowMode::Checked);
+        let mut vm = Vm::new(Program::new(prog, sym_tab), config);
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(0)));
+    }
+
+    #[test]
+    fn test_run_neg_overflow_saturating() {
+        let prog = vec![OpCode::LOADC(i64::MIN), OpCode::NEG];
+        let sym_tab = SymbolTable::new();
+        let config = VmConfig::new().overflow(OverflowMode::Saturating);
+        let mut vm = Vm::new(Program::new(prog, sym_tab), config);
+        vm.run().unwrap();
+
+        assert_eq!(vm.peek(), Some(Value::Int(i64::MAX)));
+    }
+
+    #[test]
+    fn test_run_overflow_directive() {
+        let prog = vec![
+            OpCode::OVERFLOW(OverflowMode::Checked),
+            OpCode::LOADC(1),
+            OpCode::LOADC(i64::MIN),
+            OpCode::SUB
+        ];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.run();
+
+        match result {
+            Err(VmError::IntegerOverflow(ref fault)) => assert_eq!(fault.pc, 3),
+            _ => panic!("expected integer overflow error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_div_by_zero() {
+        let prog = vec![OpCode::LOADC(0), OpCode::LOADC(5), OpCode::DIV];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let expected = VmError::DivisionByZero(Fault {
+            pc: 2,
+            line: 3,
+            op: Some(OpCode::DIV),
+            stack: vec![Value::Int(0), Value::Int(5)]
+        });
+
+        assert_eq!(vm.run(), Err(expected));
+    }
+
+    #[test]
+    fn test_run_mod_by_zero() {
+        let prog = vec![OpCode::LOADC(0), OpCode::LOADC(5), OpCode::MOD];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.run();
+
+        match result {
+            Err(VmError::DivisionByZero(ref fault)) => assert_eq!(fault.pc, 2),
+            _ => panic!("expected division by zero error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_div_by_zero_trapped() {
+        let prog = vec![
+            OpCode::TRAP("on_zero".to_string()),
+            OpCode::LOADC(0),
+            OpCode::LOADC(5),
+            OpCode::DIV,
+            OpCode::LOADC(2),
+            OpCode::ADD,
+            OpCode::HALT,
+            OpCode::LABEL("on_zero".to_string(), 7),
+            OpCode::LOADC(-1),
+            OpCode::RET
+        ];
+        let mut sym_tab = SymbolTable::new();
+        sym_tab.insert("on_zero".to_string(), 7);
+
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+
+        assert_eq!(vm.run(), Ok(ExitState::Halted));
+        assert_eq!(vm.stack, vec![Value::Int(1)]);
+    }
+
+    #[test]
+    fn test_run_trap_undefined_label() {
+        let prog = vec![OpCode::TRAP("missing".to_string())];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.run();
+
+        match result {
+            Err(VmError::UndefinedLabel(ref label, _)) => assert_eq!(label, "missing"),
+            _ => panic!("expected undefined label error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_print() {
+        let prog = vec![OpCode::PRINT("Hello!".to_string()), OpCode::EPRINT("Oops!".to_string())];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::with_io(Program::new(prog, sym_tab), VmConfig::new(), MemoryIo::new(""));
+        vm.run().unwrap();
+
+        assert_eq!(vm.io().output(), "Hello!\n");
+        assert_eq!(vm.io().errors(), "Oops!\n");
+    }
+
+    #[test]
+    fn test_run_read() {
+        let prog = vec![OpCode::READ, OpCode::READ, OpCode::ADD];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::with_io(Program::new(prog, sym_tab), VmConfig::new(), MemoryIo::new("4\n  -1 \n"));
+        vm.run().unwrap();
+
+        assert_eq!(vm.stack, vec![Value::Int(3)]);
+    }
+
+    #[test]
+    fn test_run_read_end_of_input() {
+        let prog = vec![OpCode::READ, OpCode::READ];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::with_io(Program::new(prog, sym_tab), VmConfig::new(), MemoryIo::new("4"));
+        let result = vm.run();
+
+        match result {
+            Err(VmError::EndOfInput(ref fault)) => assert_eq!(fault.pc, 1),
+            _ => panic!("expected end of input error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_read_invalid_input() {
+        let prog = vec![OpCode::READ];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::with_io(Program::new(prog, sym_tab), VmConfig::new(), MemoryIo::new("four\n"));
+        let result = vm.run();
+
+        match result {
+            Err(VmError::InvalidInput(ref line, _)) => assert_eq!(line, "four"),
+            _ => panic!("expected invalid input error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_printv() {
+        let prog = vec![OpCode::LOADC(2), OpCode::LOADC(40), OpCode::ADD, OpCode::PRINTV];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::with_io(Program::new(prog, sym_tab), VmConfig::new(), MemoryIo::new(""));
+        vm.run().unwrap();
+
+        assert_eq!(vm.io().output(), "42\n");
+        assert_eq!(vm.peek(), None);
+    }
+
+    #[test]
+    fn test_run_printv_float() {
+        let prog = vec![
+            OpCode::LOADF(f64::INFINITY),
+            OpCode::LOADF(0.5),
+            OpCode::LOADC(3),
+            OpCode::ITOF,
+            OpCode::FDIV,
+            OpCode::PRINTV,
+            OpCode::PRINTV
+        ];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::with_io(Program::new(prog, sym_tab), VmConfig::new(), MemoryIo::new(""));
+        vm.run().unwrap();
+
+        assert_eq!(vm.io().output(), "6.0\ninf\n");
+    }
+
+    #[test]
+    fn test_run_printv_underflow() {
+        let prog = vec![OpCode::PRINTV];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::with_io(Program::new(prog, sym_tab), VmConfig::new(), MemoryIo::new(""));
+        let result = vm.run();
+
+        match result {
+            Err(VmError::StackUnderflow(_)) => {},
+            _ => panic!("expected stack underflow error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_printf() {
+        let prog = vec![
+            OpCode::LOADC(7),
+            OpCode::LOADC(3),
+            OpCode::LOADC(255),
+            OpCode::PRINTF(Template::parse("x={} y={:x} z={:04b}").unwrap())
+        ];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::with_io(Program::new(prog, sym_tab), VmConfig::new(), MemoryIo::new(""));
+        vm.run().unwrap();
+
+        assert_eq!(vm.io().output(), "x=7 y=3 z=11111111\n");
+        assert_eq!(vm.peek(), None);
+    }
+
+    #[test]
+    fn test_run_printf_underflow() {
+        let prog = vec![OpCode::LOADC(7), OpCode::PRINTF(Template::parse("{} {}").unwrap())];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::with_io(Program::new(prog, sym_tab), VmConfig::new(), MemoryIo::new(""));
+        let result = vm.run();
+
+        match result {
+            Err(VmError::StackUnderflow(ref fault)) => assert_eq!(fault.stack, vec![Value::Int(7)]),
+            _ => panic!("expected stack underflow error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_printf_hex_float() {
+        let prog = vec![OpCode::LOADF(1.5), OpCode::PRINTF(Template::parse("{:x}").unwrap())];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::with_io(Program::new(prog, sym_tab), VmConfig::new(), MemoryIo::new(""));
+        let result = vm.run();
+
+        match result {
+            Err(VmError::TypeMismatch(ValueKind::Int, Value::Float(_), _)) => {},
+            _ => panic!("expected type mismatch error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_calln() {
+        let prog = vec![
+            OpCode::LOADC(7),
+            OpCode::LOADC(2),
+            OpCode::CALLN("divmod".to_string())
+        ];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.register("divmod", 2, 2, |args| match (args[0], args[1]) {
+            (Value::Int(a), Value::Int(b)) => Ok(vec![Value::Int(a / b), Value::Int(a % b)]),
+            _ => Err("expected integers".to_string())
+        });
+        vm.run().unwrap();
+
+        assert_eq!(vm.stack, vec![Value::Int(3), Value::Int(1)]);
+    }
+
+    #[test]
+    fn test_run_calln_state() {
+        let prog = vec![OpCode::CALLN("next".to_string()), OpCode::CALLN("next".to_string())];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let mut count = 0;
+        vm.register("next", 0, 1, move |_| {
+            count += 1;
+            Ok(vec![Value::Int(count)])
+        });
+        vm.run().unwrap();
+
+        assert_eq!(vm.stack, vec![Value::Int(1), Value::Int(2)]);
+    }
+
+    #[test]
+    fn test_run_calln_undefined() {
+        let prog = vec![OpCode::CALLN("missing".to_string())];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.run();
+
+        match result {
+            Err(VmError::UndefinedFunction(ref name, _)) => assert_eq!(name, "missing"),
+            _ => panic!("expected undefined function error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_calln_underflow() {
+        let prog = vec![OpCode::LOADC(1), OpCode::CALLN("pair".to_string())];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.register("pair", 2, 0, |_| Ok(vec![]));
+        let result = vm.run();
+
+        match result {
+            Err(VmError::StackUnderflow(ref fault)) => assert_eq!(fault.stack, vec![Value::Int(1)]),
+            _ => panic!("expected stack underflow error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_calln_error() {
+        let prog = vec![OpCode::LOADC(4), OpCode::CALLN("fail".to_string())];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.register("fail", 1, 0, |_| Err("no config".to_string()));
+        let result = vm.run();
+
+        assert_eq!(vm.stack, vec![Value::Int(4)]);
+
+        match result {
+            Err(VmError::Host(ref name, ref message, _)) => {
+                assert_eq!(name, "fail");
+                assert_eq!(message, "no config");
+            },
+            _ => panic!("expected host error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_run_calln_wrong_return_count() {
+        let prog = vec![OpCode::CALLN("two".to_string())];
+        let sym_tab = SymbolTable::new();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.register("two", 0, 2, |_| Ok(vec![Value::Int(1)]));
+        let result = vm.run();
+
+        match result {
+            Err(VmError::Host(_, _, _)) => {},
+            _ => panic!("expected host error, got {:?}", result)
+        }
+    }
+
+    fn invoke_prog() -> (Vec<OpCode>, SymbolTable) {
+        let prog = vec![
+            OpCode::HALT,
+            OpCode::LABEL("add".to_string(), 1),
+            OpCode::ADD,
+            OpCode::RET,
+            OpCode::LABEL("count".to_string(), 4),
+            OpCode::LOADV(0),
+            OpCode::LOADC(1),
+            OpCode::ADD,
+            OpCode::STOREV(0),
+            OpCode::RET,
+            OpCode::LABEL("stop".to_string(), 10),
+            OpCode::HALT
+        ];
+        let mut sym_tab = SymbolTable::new();
+        sym_tab.insert("add".to_string(), 1);
+        sym_tab.insert("count".to_string(), 4);
+        sym_tab.insert("stop".to_string(), 10);
+
+        (prog, sym_tab)
     }
 
-    /// Loads an address in stack memory to the top of the stack.
-    /// Requires a single argument on top of the stack, corresponding to
-    /// the memory address to load. Then, the program contents located at
-    /// that address are placed on the top of the stack.
-    ///
-    /// Consider the following sequence of operations:
-    ///
-    /// LOADC 5
-    /// LOADC 6
-    /// LOADC 1 // this is the stack location we want to load.
-    ///
-    /// The stack now looks like this:
-    ///
-    /// | 1 | <-- address to load, and sp
-    /// | 6 |
-    /// | 5 | <-- bottom of stack
-    /// +---+
-    ///
-    /// Now, we can call the load instruction:
-    ///
-    /// LOAD
-    ///
-    /// And the stack will look like the following:
-    ///
-    /// | 5 | <-- loaded value 5 from address 1
-    /// | 6 |
-    /// | 5 | <-- value at address 1 still remains the same
-    /// +---+
-    fn load(&mut self) {
-        let load_loc = util::maybe_i64_to_usize(self.stack[self.sp])
-            .unwrap_or_else(|| panic!("tyr: Attempted to load an illegal value."));
+    #[test]
+    fn test_invoke() {
+        let (prog, sym_tab) = invoke_prog();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.invoke("add", &[Value::Int(2), Value::Int(3)]);
 
-        self.stack[self.sp] = self.stack[load_loc];
+        assert_eq!(result, Ok(vec![Value::Int(5)]));
+        assert_eq!(vm.pc, 0);
+        assert!(vm.stack.is_empty());
+        assert!(vm.call_stack.is_empty());
     }
 
-    /// Convenience method provided so that we can generate
-    /// LOADV i64
-    ///
-    /// instead of
-    ///
-    /// LOADC i64
-    /// LOAD
-    fn loadv(&mut self, val: i64) {
-        self.loadc(val);
-        self.load();
+    #[test]
+    fn test_invoke_keeps_state() {
+        let (prog, sym_tab) = invoke_prog();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        assert_eq!(vm.invoke("count", &[]), Ok(vec![Value::Int(1)]));
+        assert_eq!(vm.invoke("count", &[]), Ok(vec![Value::Int(2)]));
+        assert_eq!(vm.memory[0], Value::Int(2));
     }
 
-    /// Stores a value in a specified address on the stack. This function
-    /// expects two arguments on the stack: the top value should
-    /// be the stack address of where the value will be stored, and the second
-    /// value (sp-1) should be the actual number to store. After the number
-    /// is stored, we decrement sp, removing the address argument from the stack.
-    ///
-    /// Consider the following sequence of operations:
-    ///
-    /// LOADC 5
-    /// LOADC 6 // value to store
-    /// LOADC 1 // location on stack to put it in
-    ///
-    /// Now, when we call store, we can put our value in the right spot on the stack:
-    ///
-    /// STORE
-    ///
-    /// | 6 | <- sp
-    /// | 6 | <- the previous value of 5 has been overwritten by the store call
-    /// +---+
-    fn store(&mut self) {
-        let store_loc = util::maybe_i64_to_usize(self.stack[self.sp])
-            .unwrap_or_else(|| panic!("tyr: Attempted to store an illegal value."));
+    #[test]
+    fn test_invoke_undefined_label() {
+        let (prog, sym_tab) = invoke_prog();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.invoke("missing", &[Value::Int(1)]);
 
-        self.stack[store_loc] = self.stack[self.sp - 1];
-        self.pop();
+        match result {
+            Err(VmError::UndefinedLabel(ref label, _)) => assert_eq!(label, "missing"),
+            _ => panic!("expected undefined label error, got {:?}", result)
+        }
     }
 
-    /// Convenience method provided so that we can generate
-    /// STOREV i64
-    ///
-    /// instead of
-    ///
-    /// LOADC i64
-    /// STORE
-    fn storev(&mut self, val: i64) {
-        self.loadc(val);
-        self.store();
+    #[test]
+    fn test_invoke_error_restores_state() {
+        let (prog, sym_tab) = invoke_prog();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.invoke("add", &[Value::Int(2)]);
+
+        match result {
+            Err(VmError::StackUnderflow(_)) => {},
+            _ => panic!("expected stack underflow error, got {:?}", result)
+        }
+        assert!(vm.stack.is_empty());
+        assert!(vm.call_stack.is_empty());
+        assert_eq!(vm.invoke("add", &[Value::Int(2), Value::Int(3)]), Ok(vec![Value::Int(5)]));
     }
 
-    /// Jumps to a location on the stack, by moving the program counter to the
-    /// correct address. The argument given must be a string that corresponds to
-    /// a label provided in the program.
-    /// Jmp will panic if the label provided does not exist.
-    fn jmp(&mut self, loc: String) {
-        let new_pc = self.sym_tab.get(&loc)
-            .unwrap_or_else(|| panic!("tyr: Attempted to jump to illegal location"));
+    #[test]
+    fn test_invoke_keeps_caller_stack() {
+        let (prog, sym_tab) = invoke_prog();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.stack = vec![Value::Int(7)];
+        let result = vm.invoke("add", &[Value::Int(2)]);
 
-        self.pc = *new_pc;
+        match result {
+            Err(VmError::StackUnderflow(ref fault)) => assert_eq!(fault.stack, vec![Value::Int(7), Value::Int(2)]),
+            _ => panic!("expected stack underflow error, got {:?}", result)
+        }
+        assert_eq!(vm.stack, vec![Value::Int(7)]);
+        assert_eq!(vm.invoke("add", &[Value::Int(2), Value::Int(3)]), Ok(vec![Value::Int(5)]));
+        assert_eq!(vm.stack, vec![Value::Int(7)]);
     }
 
-    /// Performs a jmp instruction, if the argument on the top of the stack is
-    /// a zero. If the value at sp is not zero, program execution continues and the
-    /// top of the stack is popped.
-    fn jmpz(&mut self, loc: String) {
-        if self.stack[self.sp] == 0 {
-            self.jmp(loc);
-        }
+    #[test]
+    fn test_invoke_after_run_undefined_label() {
+        let (prog, sym_tab) = invoke_prog();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.pc = 12;
+        assert_eq!(vm.run(), Ok(ExitState::Finished));
+        let result = vm.invoke("missing", &[]);
 
-        self.pop();
+        match result {
+            Err(VmError::UndefinedLabel(ref label, ref fault)) => {
+                assert_eq!(label, "missing");
+                assert_eq!(fault.op, None);
+            },
+            _ => panic!("expected undefined label error, got {:?}", result)
+        }
     }
 
-    /// Performs and indexed jump. This function expects a single argument on top
-    /// of the stack, an address to jump to. Then, we add the offset provided
-    /// to that address and set the program counter.
-    fn jmpi(&mut self, offset: i64) {
-        let jmp_addr = util::maybe_i64_to_usize(self.stack[self.sp] + offset)
-            .unwrap_or_else(|| panic!("tyr: Attempted to calculate an illegal jump offset"));
-        self.pc = jmp_addr;
+    #[test]
+    fn test_invoke_runs_off_end() {
+        let prog = vec![OpCode::HALT, OpCode::LABEL("tail".to_string(), 1), OpCode::NOP];
+        let mut sym_tab = SymbolTable::new();
+        sym_tab.insert("tail".to_string(), 1);
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.invoke("tail", &[]);
 
-        self.pop();
+        match result {
+            Err(VmError::NoReturn(ref label, ref fault)) => {
+                assert_eq!(label, "tail");
+                assert_eq!(fault.pc, 3);
+                assert_eq!(fault.op, None);
+            },
+            _ => panic!("expected no return error, got {:?}", result)
+        }
+        assert_eq!(vm.pc, 0);
     }
 
-    /// Duplicate the top value on the stack.
-    fn dup(&mut self) {
-        self.stack[self.sp + 1] = self.stack[self.sp];
-        self.push();
+    #[test]
+    fn test_invoke_empty_program() {
+        let mut vm = Vm::new(Program::new(Vec::new(), SymbolTable::new()), VmConfig::new());
+        let result = vm.invoke("main", &[]);
+
+        match result {
+            Err(VmError::UndefinedLabel(_, ref fault)) => assert_eq!(fault.op, None),
+            _ => panic!("expected undefined label error, got {:?}", result)
+        }
     }
 
-    fn peek(&self) -> i64 {
-        self.stack[self.sp]
+    #[test]
+    fn test_invoke_no_return() {
+        let (prog, sym_tab) = invoke_prog();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let result = vm.invoke("stop", &[]);
+
+        match result {
+            Err(VmError::NoReturn(ref label, ref fault)) => {
+                assert_eq!(label, "stop");
+                assert_eq!(fault.pc, 11);
+            },
+            _ => panic!("expected no return error, got {:?}", result)
+        }
+        assert_eq!(vm.pc, 0);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use op::OpCode;
-    use sym_tab::SymbolTable;
+    #[test]
+    fn test_run_out_of_fuel() {
+        let prog = vec![
+            OpCode::LABEL("loop".to_string(), 0),
+            OpCode::JMP("loop".to_string())
+        ];
+        let mut sym_tab = SymbolTable::new();
+        sym_tab.insert("loop".to_string(), 0);
+
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new().fuel(100));
+
+        assert_eq!(vm.run(), Ok(ExitState::OutOfFuel));
+        assert_eq!(vm.fuel(), Some(0));
+    }
 
     #[test]
-    fn test_run_loadc() {
-        let prog = vec![OpCode::LOADC(5)];
+    fn test_run_fuel_resume() {
+        let prog = vec![OpCode::LOADC(1), OpCode::LOADC(2), OpCode::ADD, OpCode::LOADC(3)];
         let sym_tab = SymbolTable::new();
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new().fuel(5).fuel_cost(2));
+
+        assert_eq!(vm.run(), Ok(ExitState::OutOfFuel));
+        assert_eq!(vm.pc, 2);
+        assert_eq!(vm.fuel(), Some(1));
 
-        assert_eq!(vm.peek(), 5);
+        vm.add_fuel(3);
+        assert_eq!(vm.run(), Ok(ExitState::Finished));
+        assert_eq!(vm.stack, vec![Value::Int(3), Value::Int(3)]);
+        assert_eq!(vm.fuel(), Some(0));
     }
 
     #[test]
-    fn test_run_add() {
-        let prog = vec![OpCode::LOADC(5), OpCode::LOADC(5), OpCode::ADD];
+    fn test_run_unlimited_fuel() {
+        let prog = vec![OpCode::LOADC(1); 3];
         let sym_tab = SymbolTable::new();
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
 
-        assert_eq!(vm.peek(), 10);
+        assert_eq!(vm.run(), Ok(ExitState::Finished));
+        assert_eq!(vm.fuel(), None);
     }
 
     #[test]
-    fn test_run_sub() {
-        let prog = vec![OpCode::LOADC(5), OpCode::LOADC(4), OpCode::SUB];
-        let sym_tab = SymbolTable::new();
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
+    fn test_invoke_out_of_fuel() {
+        let (prog, sym_tab) = invoke_prog();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new().fuel(1));
+        let result = vm.invoke("add", &[Value::Int(2), Value::Int(3)]);
 
-        assert_eq!(vm.peek(), -1);
+        match result {
+            Err(VmError::NoReturn(_, ref fault)) => assert_eq!(fault.pc, 2),
+            _ => panic!("expected no return error, got {:?}", result)
+        }
     }
 
     #[test]
-    fn test_run_mul() {
-        let prog = vec![OpCode::LOADC(5), OpCode::LOADC(5), OpCode::MUL];
+    fn test_run_interrupted() {
+        let prog = vec![OpCode::LOADC(1), OpCode::LOADC(2), OpCode::ADD];
         let sym_tab = SymbolTable::new();
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.interrupt_handle().interrupt();
 
-        assert_eq!(vm.peek(), 25);
+        assert_eq!(vm.run(), Ok(ExitState::Interrupted));
+        assert_eq!(vm.pc, 0);
+
+        assert_eq!(vm.run(), Ok(ExitState::Finished));
+        assert_eq!(vm.peek(), Some(Value::Int(3)));
     }
 
     #[test]
-    fn test_run_div() {
-        let prog = vec![OpCode::LOADC(5), OpCode::LOADC(5), OpCode::DIV];
+    fn test_run_interrupted_at_end() {
+        let prog = vec![OpCode::LOADC(1)];
         let sym_tab = SymbolTable::new();
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+        vm.interrupt_handle().interrupt();
 
-        assert_eq!(vm.peek(), 1);
+        assert_eq!(vm.run(), Ok(ExitState::Interrupted));
+        assert_eq!(vm.run(), Ok(ExitState::Finished));
     }
 
     #[test]
-    fn test_run_div_with_remainder() {
-        let prog = vec![OpCode::LOADC(3), OpCode::LOADC(10), OpCode::DIV];
+    fn test_run_interrupted_from_thread() {
+        use std::thread;
+
+        let prog = vec![
+            OpCode::LABEL("loop".to_string(), 0),
+            OpCode::LOADC(1),
+            OpCode::DROP,
+            OpCode::JMP("loop".to_string())
+        ];
+        let mut sym_tab = SymbolTable::new();
+        sym_tab.insert("loop".to_string(), 0);
+
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let handle = vm.interrupt_handle();
+        let thread = thread::spawn(move || handle.interrupt());
+
+        assert_eq!(vm.run(), Ok(ExitState::Interrupted));
+        assert!(vm.pc < vm.program.len());
+        assert!(vm.stack.len() <= 1);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_step() {
+        let prog = vec![OpCode::LOADC(2), OpCode::LOADC(3), OpCode::ADD, OpCode::HALT];
         let sym_tab = SymbolTable::new();
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+
+        assert_eq!(vm.step(), StepOutcome::Continue);
+        assert_eq!(vm.step(), StepOutcome::Continue);
+        assert_eq!(vm.pc(), 2);
+        assert_eq!(vm.sp(), 2);
+        assert_eq!(vm.current_instruction(), Some(&OpCode::ADD));
 
-        assert_eq!(vm.peek(), 3);
+        assert_eq!(vm.step(), StepOutcome::Continue);
+        assert_eq!(vm.stack(), &[Value::Int(5)]);
+        assert_eq!(vm.step(), StepOutcome::Halted);
     }
 
     #[test]
-    fn test_run_modq() {
-        let prog = vec![OpCode::LOADC(2), OpCode::LOADC(4), OpCode::MOD];
+    fn test_step_finished() {
+        let prog = vec![OpCode::NOP];
         let sym_tab = SymbolTable::new();
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
 
-        assert_eq!(vm.peek(), 0);
+        assert_eq!(vm.step(), StepOutcome::Continue);
+        assert_eq!(vm.current_instruction(), None);
+        assert_eq!(vm.step(), StepOutcome::Finished);
     }
 
     #[test]
-    fn test_run_and() {
-        let prog = vec![OpCode::LOADC(2), OpCode::LOADC(2), OpCode::AND];
+    fn test_step_error() {
+        let prog = vec![OpCode::LOADC(1), OpCode::ADD];
         let sym_tab = SymbolTable::new();
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.step();
 
-        assert_eq!(vm.peek(), 2);
+        match vm.step() {
+            StepOutcome::Error(VmError::StackUnderflow(_)) => {},
+            outcome => panic!("expected stack underflow error, got {:?}", outcome)
+        }
+        assert_eq!(vm.pc(), 1);
     }
 
     #[test]
-    fn test_run_or() {
-        let prog = vec![OpCode::LOADC(3), OpCode::LOADC(2), OpCode::OR];
+    fn test_step_out_of_fuel() {
+        let prog = vec![OpCode::NOP, OpCode::NOP];
         let sym_tab = SymbolTable::new();
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new().fuel(1));
 
-        assert_eq!(vm.peek(), 3);
+        assert_eq!(vm.step(), StepOutcome::Continue);
+        assert_eq!(vm.step(), StepOutcome::OutOfFuel);
+        assert_eq!(vm.pc(), 1);
     }
 
     #[test]
-    fn test_run_neg() {
-        let prog = vec![OpCode::LOADC(5), OpCode::NEG];
+    fn test_run_breakpoint_pc() {
+        let prog = vec![OpCode::LOADC(1), OpCode::LOADC(2), OpCode::ADD];
         let sym_tab = SymbolTable::new();
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        assert_eq!(vm.add_breakpoint(Breakpoint::Pc(1)), Some(1));
 
-        assert_eq!(vm.peek(), -5);
+        assert_eq!(vm.run(), Ok(ExitState::Paused(Pause::Breakpoint(1))));
+        assert_eq!(vm.stack(), &[Value::Int(1)]);
+        assert_eq!(vm.run(), Ok(ExitState::Finished));
+        assert_eq!(vm.peek(), Some(Value::Int(3)));
     }
 
     #[test]
-    fn test_run_load() {
-        let prog = vec![OpCode::LOADC(5), OpCode::LOADC(4), OpCode::LOADC(1), OpCode::LOAD];
+    fn test_run_breakpoint_label() {
+        let prog = vec![
+            OpCode::LOADC(0),
+            OpCode::LABEL("loop".to_string(), 2),
+            OpCode::LOADC(1),
+            OpCode::ADD,
+            OpCode::DUP,
+            OpCode::LOADC(3),
+            OpCode::JGT("loop".to_string())
+        ];
+        let mut sym_tab = SymbolTable::new();
+        sym_tab.insert("loop".to_string(), 2);
+
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.add_breakpoint(Breakpoint::Label("loop".to_string()));
+
+        let mut pauses = 0;
+        while let Ok(ExitState::Paused(Pause::Breakpoint(2))) = vm.run() {
+            pauses += 1;
+        }
+
+        assert_eq!(pauses, 3);
+        assert_eq!(vm.peek(), Some(Value::Int(3)));
+    }
+
+    #[test]
+    fn test_run_breakpoint_removed() {
+        let prog = vec![OpCode::NOP, OpCode::NOP];
         let sym_tab = SymbolTable::new();
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.add_breakpoint(Breakpoint::Line(2));
 
-        assert_eq!(vm.peek(), 5);
+        assert!(vm.remove_breakpoint(Breakpoint::Pc(1)));
+        assert_eq!(vm.run(), Ok(ExitState::Finished));
     }
 
     #[test]
-    fn test_run_load_no_contents() {
-        let prog = vec![OpCode::LOADC(5), OpCode::LOADC(5), OpCode::LOADC(4), OpCode::LOAD];
+    fn test_run_breakpoint_out_of_fuel() {
+        let prog = vec![OpCode::LOADC(1), OpCode::LOADC(2)];
         let sym_tab = SymbolTable::new();
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
-        // Load will return 0 if there are no contents in the stack address attempted to load.
-        assert_eq!(vm.peek(), 0);
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new().fuel(1));
+        vm.add_breakpoint(Breakpoint::Pc(1));
+
+        assert_eq!(vm.run(), Ok(ExitState::Paused(Pause::Breakpoint(1))));
+        assert_eq!(vm.run(), Ok(ExitState::OutOfFuel));
+        vm.add_fuel(1);
+        assert_eq!(vm.run(), Ok(ExitState::Finished));
+        assert_eq!(vm.stack(), &[Value::Int(1), Value::Int(2)]);
     }
 
     #[test]
-    #[should_panic(expected = "tyr: Attempted to load an illegal value.")]
-    fn test_run_load_illegal_value() {
-        let prog = vec![OpCode::LOADC(5), OpCode::LOADC(5), OpCode::LOADC(-2), OpCode::LOAD];
+    fn test_invoke_breakpoint() {
+        let (prog, sym_tab) = invoke_prog();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.add_breakpoint(Breakpoint::Label("add".to_string()));
+
+        assert!(vm.invoke("add", &[Value::Int(2), Value::Int(3)]).is_err());
+        assert!(vm.invoke("add", &[Value::Int(2), Value::Int(3)]).is_err());
+        vm.add_breakpoint(Breakpoint::Pc(0));
+        assert_eq!(vm.run(), Ok(ExitState::Paused(Pause::Breakpoint(0))));
+    }
+
+    #[test]
+    fn test_add_breakpoint_invalid() {
+        let prog = vec![OpCode::NOP];
         let sym_tab = SymbolTable::new();
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+
+        assert_eq!(vm.add_breakpoint(Breakpoint::Label("missing".to_string())), None);
+        assert_eq!(vm.add_breakpoint(Breakpoint::Line(0)), None);
+        assert_eq!(vm.add_breakpoint(Breakpoint::Pc(1)), None);
     }
 
     #[test]
-    fn test_run_loadv() {
-        let prog = vec![OpCode::LOADC(3), OpCode::LOADV(1)];
+    fn test_run_watchpoint_memory() {
+        let prog = vec![OpCode::LOADC(7), OpCode::STOREV(3), OpCode::LOADC(8), OpCode::STOREV(4)];
         let sym_tab = SymbolTable::new();
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.add_watchpoint(Watchpoint::Memory(4));
 
-        assert_eq!(vm.peek(), 3);
+        assert_eq!(vm.run(), Ok(ExitState::Paused(Pause::Watchpoint(Watchpoint::Memory(4)))));
+        assert_eq!(vm.pc(), 4);
+        assert_eq!(vm.run(), Ok(ExitState::Finished));
     }
 
     #[test]
-    fn test_run_store() {
-        let prog = vec![OpCode::LOADC(5), OpCode::LOADC(4), OpCode::LOADC(1), OpCode::STORE];
+    fn test_run_watchpoint_stack() {
+        let prog = vec![OpCode::LOADC(1), OpCode::LOADC(2), OpCode::SWAP, OpCode::DROP];
         let sym_tab = SymbolTable::new();
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.add_watchpoint(Watchpoint::Stack(0));
 
-        assert_eq!(vm.peek(), 4);
+        assert_eq!(vm.run(), Ok(ExitState::Paused(Pause::Watchpoint(Watchpoint::Stack(0)))));
+        assert_eq!(vm.pc(), 1);
+        assert_eq!(vm.run(), Ok(ExitState::Paused(Pause::Watchpoint(Watchpoint::Stack(0)))));
+        assert_eq!(vm.pc(), 3);
+        assert_eq!(vm.stack(), &[Value::Int(2), Value::Int(1)]);
+        assert_eq!(vm.run(), Ok(ExitState::Finished));
     }
 
     #[test]
-    #[should_panic(expected = "tyr: Attempted to store an illegal value.")]
-    fn test_run_store_illegal_value() {
-        let prog = vec![OpCode::LOADC(5), OpCode::LOADC(5), OpCode::LOADC(-1), OpCode::STORE];
+    fn test_step_breakpoint() {
+        let prog = vec![OpCode::NOP, OpCode::NOP];
         let sym_tab = SymbolTable::new();
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.add_breakpoint(Breakpoint::Pc(0));
+
+        assert_eq!(vm.step(), StepOutcome::Paused(Pause::Breakpoint(0)));
+        assert_eq!(vm.step(), StepOutcome::Continue);
+        assert_eq!(vm.pc(), 1);
+    }
+
+    /// A writer that keeps what was written where the test can see it,
+    /// after the writer itself has been handed to the vm.
+    #[derive(Clone, Default)]
+    struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuf {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+                .lines()
+                .map(|line| line.to_string())
+                .collect()
+        }
+    }
+
+    struct FailingFlush;
+
+    impl Write for FailingFlush {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+        }
     }
 
     #[test]
-    fn test_run_storev() {
-        let prog = vec![OpCode::LOADC(5),  OpCode::STOREV(1)];
+    fn test_run_trace_flush_error() {
+        let prog = vec![OpCode::LOADC(1)];
         let sym_tab = SymbolTable::new();
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.set_tracer(Tracer::new(FailingFlush));
+        let result = vm.run();
 
-        assert_eq!(vm.peek(), 5);
+        match result {
+            Err(VmError::Io(io::ErrorKind::BrokenPipe, ref fault)) => {
+                assert_eq!(fault.pc, 1);
+                assert_eq!(fault.op, None);
+            },
+            _ => panic!("expected io error, got {:?}", result)
+        }
     }
 
     #[test]
-    fn test_run_jmp() {
+    fn test_run_trace() {
         let prog = vec![
-            OpCode::LABEL("label1".to_string(), 1),
-            OpCode::LOADC(5),
-            OpCode::LOADC(6),
-            OpCode::JMP("halt".to_string()),
-            OpCode::LOADC(7),
-            OpCode::LABEL("halt".to_string(), 5)
+            OpCode::LOADC(2),
+            OpCode::JMP("end".to_string()),
+            OpCode::NOP,
+            OpCode::LABEL("end".to_string(), 3),
+            OpCode::LOADC(3),
+            OpCode::ADD
         ];
         let mut sym_tab = SymbolTable::new();
-        sym_tab.insert("label1".to_string(), 1);
-        sym_tab.insert("halt".to_string(), 5);
+        sym_tab.insert("end".to_string(), 3);
 
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
+        let buf = SharedBuf::default();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.set_tracer(Tracer::new(buf.clone()));
+        vm.run().unwrap();
 
-        assert_eq!(vm.peek(), 6);
+        assert_eq!(buf.lines(), vec![
+            r#"{"pc":0,"op":"LOADC 2","before":null,"after":2,"jump":null}"#,
+            r#"{"pc":1,"op":"JMP end","before":2,"after":2,"jump":3}"#,
+            r#"{"pc":3,"op":"end:","before":2,"after":2,"jump":null}"#,
+            r#"{"pc":4,"op":"LOADC 3","before":2,"after":3,"jump":null}"#,
+            r#"{"pc":5,"op":"ADD","before":3,"after":5,"jump":null}"#
+        ]);
     }
 
     #[test]
-    #[should_panic(expected = "tyr: Attempted to jump to illegal location")]
-    fn test_run_jmp_no_label() {
-        let prog = vec![
-            OpCode::LABEL("label1".to_string(), 1),
-            OpCode::LOADC(5),
-            OpCode::LOADC(6),
-            OpCode::JMP("label2".to_string())
-        ];
+    fn test_run_trace_range() {
+        let prog = vec![OpCode::LOADC(1), OpCode::LOADC(2), OpCode::ADD, OpCode::DROP];
         let sym_tab = SymbolTable::new();
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
+
+        let buf = SharedBuf::default();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.set_tracer(Tracer::new(buf.clone()).range(1..3));
+        vm.run().unwrap();
+
+        assert_eq!(buf.lines(), vec![
+            r#"{"pc":1,"op":"LOADC 2","before":1,"after":2,"jump":null}"#,
+            r#"{"pc":2,"op":"ADD","before":2,"after":3,"jump":null}"#
+        ]);
     }
 
     #[test]
-    fn test_run_jmpz() {
+    fn test_run_trace_fault() {
+        let prog = vec![OpCode::LOADC(0), OpCode::LOADC(1), OpCode::DIV, OpCode::NOP];
+        let sym_tab = SymbolTable::new();
+
+        let buf = SharedBuf::default();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.set_tracer(Tracer::new(buf.clone()).range(2..4));
+        let result = vm.run();
+
+        match result {
+            Err(VmError::DivisionByZero(_)) => {},
+            _ => panic!("expected division by zero error, got {:?}", result)
+        }
+        assert_eq!(buf.lines(), vec![
+            r#"{"pc":2,"op":"DIV","before":1,"after":null,"jump":null,"error":"tyr [line 3, pc 2, DIV]: Division by zero"}"#
+        ]);
+    }
+
+    #[test]
+    fn test_run_profile() {
         let prog = vec![
-            OpCode::LABEL("label1".to_string(), 1),
-            OpCode::LOADC(5),
-            OpCode::LOADC(6),
-            OpCode::LOADC(0),
-            OpCode::JMPZ("halt".to_string()),
-            OpCode::LABEL("halt".to_string(), 5),
-            OpCode::LOADC(7)
+            OpCode::LOADC(3),
+            OpCode::CALL("square".to_string()),
+            OpCode::HALT,
+            OpCode::LABEL("square".to_string(), 3),
+            OpCode::DUP,
+            OpCode::MUL,
+            OpCode::RET
         ];
         let mut sym_tab = SymbolTable::new();
-        sym_tab.insert("label1".to_string(), 1);
-        sym_tab.insert("halt".to_string(), 5);
+        sym_tab.insert("square".to_string(), 3);
 
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
+        let profiler = Profiler::new(&sym_tab);
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.set_profiler(profiler);
+        vm.run().unwrap();
 
-        assert_eq!(vm.peek(), 7);
+        let profiler = vm.profiler().unwrap();
+        assert_eq!(profiler.pc_count(4), 1);
+        assert_eq!(profiler.label_counts().get("square"), Some(&4));
+        assert_eq!(profiler.label_counts().get("main"), Some(&3));
+        assert_eq!(profiler.folded(), "main 3\nmain;square 4\n");
     }
 
     #[test]
-    fn test_run_jmpz_not_zero() {
+    fn test_snapshot_restore() {
         let prog = vec![
-            OpCode::LABEL("label1".to_string(), 1),
-            OpCode::LOADC(5),
-            OpCode::LOADC(6),
-            OpCode::LOADC(1),
-            OpCode::JMPZ("label1".to_string())
+            OpCode::LOADC(4),
+            OpCode::STOREV(2),
+            OpCode::ALLOC(2),
+            OpCode::LOADF(1.5),
+            OpCode::CALL("sub".to_string()),
+            OpCode::HALT,
+            OpCode::LABEL("sub".to_string(), 6),
+            OpCode::NOP,
+            OpCode::RET
         ];
+        let mut sym_tab = SymbolTable::new();
+        sym_tab.insert("sub".to_string(), 6);
+
+        let program = Arc::new(Program::new(prog, sym_tab));
+        let mut vm = Vm::new(Arc::clone(&program), VmConfig::new());
+        vm.add_breakpoint(Breakpoint::Pc(7));
+        assert_eq!(vm.run(), Ok(ExitState::Paused(Pause::Breakpoint(7))));
+        let snapshot = vm.snapshot();
+
+        let mut other = Vm::new(program, VmConfig::new().overflow(OverflowMode::Checked));
+        assert_eq!(other.restore(&snapshot), Ok(()));
+
+        assert_eq!(other.pc, 7);
+        assert_eq!(other.stack, vm.stack);
+        assert_eq!(other.memory[2], Value::Int(4));
+        assert_eq!(other.call_stack, vec![5]);
+        assert_eq!(other.overflow, OverflowMode::Wrapping);
+        assert_eq!(other.heap.image(), vm.heap.image());
+        assert_eq!(other.snapshot(), snapshot);
+        assert_eq!(other.run(), Ok(ExitState::Halted));
+    }
+
+    #[test]
+    fn test_restore_program_mismatch() {
+        let prog = vec![OpCode::LOADC(1)];
+        let other_prog = vec![OpCode::LOADC(2)];
+
+        let vm = Vm::new(Program::new(prog, SymbolTable::new()), VmConfig::new());
+        let mut other = Vm::new(Program::new(other_prog, SymbolTable::new()), VmConfig::new());
+
+        assert_eq!(other.restore(&vm.snapshot()), Err(SnapshotError::ProgramMismatch));
+    }
+
+    #[test]
+    fn test_restore_invalid() {
+        let prog = vec![OpCode::LOADC(1)];
         let sym_tab = SymbolTable::new();
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        let mut snapshot = vm.snapshot();
+        let len = snapshot.len();
+
+        assert_eq!(vm.restore(&snapshot[..len - 1]), Err(SnapshotError::Truncated));
+        assert!(vm.restore(b"nope").is_err());
 
-        assert_eq!(vm.peek(), 6);
+        snapshot[4] = 9;
+        assert_eq!(vm.restore(&snapshot), Err(SnapshotError::UnsupportedVersion(9)));
+        assert_eq!(vm.stack, vec![Value::Int(1)]);
     }
 
     #[test]
-    fn test_run_jmpi() {
-        let prog = vec![
-            OpCode::LOADC(5),
-            OpCode::LOADC(6),
-            OpCode::LOADC(1),
-            OpCode::JMPI(3),
-            OpCode::LOADC(4),
-            OpCode::HALT
-        ];
+    fn test_restore_corrupt_heap() {
+        let prog = vec![OpCode::ALLOC(3)];
         let sym_tab = SymbolTable::new();
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        vm.run().unwrap();
+
+        let mut snapshot = vm.snapshot();
+        let len = snapshot.len();
+        snapshot[len - 8] = 2;
+        let result = vm.restore(&snapshot);
 
-        assert_eq!(vm.peek(), 4);
+        match result {
+            Err(SnapshotError::InvalidFormat(_)) => {},
+            _ => panic!("expected invalid format error, got {:?}", result)
+        }
+        assert_eq!(vm.heap().image().next_addr, 4);
     }
 
     #[test]
-    fn test_run_dup() {
-        let prog = vec![OpCode::LOADC(5), OpCode::DUP];
+    fn test_program_entry() {
+        let prog = vec![OpCode::LOADC(1), OpCode::LOADC(2), OpCode::HALT];
+        let program = Program::new(prog, SymbolTable::new()).entry(1);
+        let mut vm = Vm::new(program, VmConfig::new());
+
+        assert_eq!(vm.pc(), 1);
+        assert_eq!(vm.run(), Ok(ExitState::Halted));
+        assert_eq!(vm.stack, vec![Value::Int(2)]);
+    }
+
+    #[test]
+    fn test_fault_source_line() {
+        let prog = vec![OpCode::NOP, OpCode::ADD];
+        let program = Program::new(prog, SymbolTable::new()).lines(vec![3, 7]);
+        let mut vm = Vm::new(program, VmConfig::new());
+
+        match vm.run() {
+            Err(VmError::StackUnderflow(fault)) => {
+                assert_eq!(fault.pc, 1);
+                assert_eq!(fault.line, 7);
+            },
+            result => panic!("expected stack underflow error, got {:?}", result)
+        }
+    }
+
+    #[test]
+    fn test_shared_program() {
+        use std::thread;
+
+        let prog = vec![OpCode::LOADC(20), OpCode::LOADC(22), OpCode::ADD];
+        let program = Arc::new(Program::new(prog, SymbolTable::new()));
+
+        let threads: Vec<_> = (0..2).map(|_| {
+            let program = Arc::clone(&program);
+            thread::spawn(move || {
+                let mut vm = Vm::with_io(program, VmConfig::new(), MemoryIo::new(""));
+                vm.run().unwrap();
+                vm.peek()
+            })
+        }).collect();
+
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), Some(Value::Int(42)));
+        }
+    }
+
+    #[test]
+    fn test_fault_keeps_operands() {
+        let prog = vec![OpCode::LOADC(1), OpCode::LOADF(2.0), OpCode::LOADC(3), OpCode::ADD];
         let sym_tab = SymbolTable::new();
-        let mut vm = Vm::new(&prog, &sym_tab);
-        vm.run();
+        let mut vm = Vm::new(Program::new(prog, sym_tab), VmConfig::new());
+        let stack = vec![Value::Int(1), Value::Float(2.0), Value::Int(3)];
+        for _ in 0..3 {
+            vm.step();
+        }
 
-        assert_eq!(vm.peek(), 5);
+        for _ in 0..2 {
+            match vm.step() {
+                StepOutcome::Error(VmError::TypeMismatch(_, _, ref fault)) => assert_eq!(fault.stack, stack),
+                outcome => panic!("expected type mismatch error, got {:?}", outcome)
+            }
+            assert_eq!(vm.stack, stack);
+            assert_eq!(vm.pc, 3);
+        }
     }
 }
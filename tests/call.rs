@@ -0,0 +1,15 @@
+mod common;
+
+#[test]
+fn test_call_valid() {
+    let result = common::run_test_output("tests/input/call.test");
+
+    assert_eq!(result, "Hello!\nHello!\n");
+}
+
+#[test]
+fn test_call_status() {
+    let result = common::run_test_status("tests/input/call.test");
+
+    assert!(result.success());
+}
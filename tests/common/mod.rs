@@ -1,13 +1,23 @@
+extern crate tyr;
+
 use std::process::{Command, ExitStatus};
+use self::tyr::config::VmConfig;
+use self::tyr::io::MemoryIo;
+use self::tyr::util;
+use self::tyr::vm::Vm;
 
-pub fn run_test_output(filename: &str) -> String {
-    let output = Command::new("cargo")
-        .arg("run")
-        .arg(filename)
-        .output()
-        .expect("failed to run test:");
+/// Runs a program in this process, and returns the vm once it stops.
+pub fn run_test_vm(filename: &str) -> Vm<MemoryIo> {
+    let prog = util::read_file(filename.to_string());
+    let mut vm = Vm::with_io(prog, VmConfig::new(), MemoryIo::new(""));
+    let _ = vm.run();
 
-    String::from_utf8(output.stdout).ok().unwrap()
+    vm
+}
+
+/// Runs a program in this process, and returns what it wrote to stdout.
+pub fn run_test_output(filename: &str) -> String {
+    run_test_vm(filename).io().output()
 }
 
 pub fn run_test_status(filename: &str) -> ExitStatus {
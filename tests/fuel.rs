@@ -0,0 +1,17 @@
+use std::process::Command;
+
+#[test]
+fn test_fuel_stops_loop() {
+    let output = Command::new("cargo")
+        .arg("run")
+        .arg("--")
+        .arg("--fuel")
+        .arg("1000")
+        .arg("tests/input/fuel.test")
+        .output()
+        .expect("failed to run test:");
+    let stderr = String::from_utf8(output.stderr).ok().unwrap();
+
+    assert!(!output.status.success());
+    assert!(stderr.contains("tyr: Ran out of fuel"));
+}
@@ -0,0 +1,40 @@
+extern crate tyr;
+
+mod common;
+
+use std::process::Command;
+use tyr::heap::Leak;
+
+#[test]
+fn test_heap_leak_reported() {
+    let vm = common::run_test_vm("tests/input/leak.test");
+
+    assert_eq!(vm.heap().leaks(), vec![Leak { addr: 1, size: 3 }]);
+}
+
+#[test]
+fn test_heap_leak_output() {
+    let result = common::run_test_output("tests/input/leak.test");
+
+    assert_eq!(result, "Allocated!\n");
+}
+
+#[test]
+fn test_heap_leak_status() {
+    let result = common::run_test_status("tests/input/leak.test");
+
+    assert!(result.success());
+}
+
+#[test]
+fn test_heap_leak_stderr() {
+    let output = Command::new("cargo")
+        .arg("run")
+        .arg("tests/input/leak.test")
+        .output()
+        .expect("failed to run test:");
+    let stderr = String::from_utf8(output.stderr).ok().unwrap();
+
+    assert!(output.status.success());
+    assert!(stderr.contains("tyr: Leaked 3 heap cells at address 1"));
+}
@@ -0,0 +1,34 @@
+extern crate tyr;
+
+use tyr::config::VmConfig;
+use tyr::io::MemoryIo;
+use tyr::util;
+use tyr::vm::{ExitState, Vm};
+
+/// Runs tests/input/read.test with the given input, returning how it
+/// finished along with what it wrote to stdout and stderr.
+fn run_read_test(input: &str) -> (ExitState, String, String) {
+    let prog = util::read_file("tests/input/read.test".to_string());
+    let mut vm = Vm::with_io(prog, VmConfig::new(), MemoryIo::new(input));
+    let state = vm.run().unwrap();
+
+    (state, vm.io().output(), vm.io().errors())
+}
+
+#[test]
+fn test_read_sum() {
+    let (state, output, errors) = run_read_test("2\n3\n");
+
+    assert_eq!(state, ExitState::Halted);
+    assert_eq!(output, "Numbers?\n");
+    assert_eq!(errors, "");
+}
+
+#[test]
+fn test_read_sum_zero() {
+    let (state, output, errors) = run_read_test("2\n-2\n");
+
+    assert_eq!(state, ExitState::Finished);
+    assert_eq!(output, "Numbers?\n");
+    assert_eq!(errors, "Zero!\n");
+}
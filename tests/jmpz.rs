@@ -11,5 +11,5 @@ fn test_jmpz_valid() {
 fn test_jmpz_invalid() {
     let result = common::run_test_status("tests/input/jmpz.test");
 
-    assert_eq!(result.success(), true);
+    assert!(result.success());
 }
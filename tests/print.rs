@@ -0,0 +1,15 @@
+mod common;
+
+#[test]
+fn test_print_output() {
+    let result = common::run_test_output("tests/input/print.test");
+
+    assert_eq!(result, "42\nanswer=0042 hex=A bits=1010\nHello, world!\tbye\n");
+}
+
+#[test]
+fn test_print_status() {
+    let result = common::run_test_status("tests/input/print.test");
+
+    assert!(result.success());
+}
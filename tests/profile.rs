@@ -0,0 +1,28 @@
+use std::env;
+use std::fs;
+use std::process::{self, Command};
+
+#[test]
+fn test_profile_files_written() {
+    let report_path = env::temp_dir().join(format!("tyr_profile_test_{}.txt", process::id()));
+    let folded_path = env::temp_dir().join(format!("tyr_profile_test_{}.folded", process::id()));
+    let status = Command::new("cargo")
+        .arg("run")
+        .arg("--")
+        .arg("--profile")
+        .arg(&report_path)
+        .arg("--folded")
+        .arg(&folded_path)
+        .arg("tests/input/call.test")
+        .status()
+        .expect("failed to run test:");
+    let report = fs::read_to_string(&report_path).unwrap();
+    let folded = fs::read_to_string(&folded_path).unwrap();
+    fs::remove_file(&report_path).unwrap();
+    fs::remove_file(&folded_path).unwrap();
+
+    assert!(status.success());
+    assert!(report.starts_with("Instructions executed: 8\n"));
+    assert!(report.contains("By label:"));
+    assert_eq!(folded, "main 4\nmain;greet 4\n");
+}
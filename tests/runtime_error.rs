@@ -0,0 +1,15 @@
+mod common;
+
+#[test]
+fn test_runtime_error_output() {
+    let result = common::run_test_output("tests/input/underflow.test");
+
+    assert_eq!(result, "");
+}
+
+#[test]
+fn test_runtime_error_status() {
+    let result = common::run_test_status("tests/input/underflow.test");
+
+    assert!(!result.success());
+}
@@ -0,0 +1,28 @@
+use std::env;
+use std::fs;
+use std::process::{self, Command};
+
+#[test]
+fn test_trace_file_written() {
+    let path = env::temp_dir().join(format!("tyr_trace_test_{}.jsonl", process::id()));
+    let status = Command::new("cargo")
+        .arg("run")
+        .arg("--")
+        .arg("--trace")
+        .arg(&path)
+        .arg("--trace-from")
+        .arg("start")
+        .arg("--trace-to")
+        .arg("end")
+        .arg("tests/input/trace.test")
+        .status()
+        .expect("failed to run test:");
+    let trace = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert!(status.success());
+    assert_eq!(trace, concat!(
+        "{\"pc\":2,\"op\":\"LOADC 2\",\"before\":1,\"after\":2,\"jump\":null}\n",
+        "{\"pc\":3,\"op\":\"ADD\",\"before\":2,\"after\":3,\"jump\":null}\n"
+    ));
+}
@@ -0,0 +1,15 @@
+mod common;
+
+#[test]
+fn test_trap_output() {
+    let result = common::run_test_output("tests/input/trap.test");
+
+    assert_eq!(result, "Recovered\n");
+}
+
+#[test]
+fn test_trap_status() {
+    let result = common::run_test_status("tests/input/trap.test");
+
+    assert!(result.success());
+}